@@ -7,8 +7,8 @@ use std::process::Command;
 fn get_git_version() -> String {
     let version = env::var("CARGO_PKG_VERSION").unwrap().to_string();
 
-    let child = Command::new("git").args(&["describe", "--always"]).output();
-    return match child {
+    let child = Command::new("git").args(["describe", "--always"]).output();
+    match child {
         Ok(child) => {
             let buf = String::from_utf8(child.stdout).expect("failed to read stdout");
             version + "-" + &buf
@@ -17,7 +17,7 @@ fn get_git_version() -> String {
             eprintln!("`git describe` err: {}", err);
             version
         }
-    };
+    }
 }
 
 fn main() {
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::process::Command;
+use tokio::time;
+
+// 常量定义
+const SCRIPT_TIMEOUT_SECS: u64 = 5; // 单个脚本执行超时时间
+
+/// 自定义采集脚本
+/// 只有通过 `--collector-script` 显式指定的脚本才会被执行
+#[derive(Debug, Clone)]
+pub struct CollectorScript {
+    /// 脚本路径
+    pub path: PathBuf,
+    /// 脚本名称，作为指标名前缀
+    pub name: String,
+}
+
+impl FromStr for CollectorScript {
+    type Err = String;
+
+    /// 解析 `path:name` 格式的参数
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, name) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("采集脚本格式错误，应为 path:name: {}", s))?;
+        if path.is_empty() || name.is_empty() {
+            return Err(format!("采集脚本路径和名称不能为空: {}", s));
+        }
+        Ok(Self {
+            path: PathBuf::from(path),
+            name: name.to_string(),
+        })
+    }
+}
+
+impl CollectorScript {
+    /// 执行脚本并解析输出
+    /// 脚本需向标准输出打印 `{"指标名": 数值}` 格式的 JSON
+    async fn run(&self, timeout: Duration) -> anyhow::Result<HashMap<String, f64>> {
+        let output = Command::new(&self.path)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = time::timeout(timeout, output)
+            .await
+            .map_err(|_| anyhow::anyhow!("执行超时"))??;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!("退出码异常: {}", output.status));
+        }
+
+        let metrics = serde_json::from_slice::<HashMap<String, f64>>(&output.stdout)
+            .map_err(|e| anyhow::anyhow!("输出格式错误: {}", e))?;
+        Ok(metrics
+            .into_iter()
+            .map(|(metric, value)| (format!("{}.{}", self.name, metric), value))
+            .collect())
+    }
+}
+
+/// 并发执行所有采集脚本并合并结果
+/// 执行失败或输出格式错误的脚本会被跳过
+pub async fn collect_custom_metrics(scripts: &[CollectorScript]) -> HashMap<String, f64> {
+    collect_with_timeout(scripts, Duration::from_secs(SCRIPT_TIMEOUT_SECS)).await
+}
+
+/// 并发执行所有采集脚本，超过 `timeout` 的脚本被终止并跳过
+async fn collect_with_timeout(
+    scripts: &[CollectorScript],
+    timeout: Duration,
+) -> HashMap<String, f64> {
+    let results = futures::future::join_all(scripts.iter().map(|script| script.run(timeout))).await;
+
+    let mut metrics = HashMap::new();
+    for (script, result) in scripts.iter().zip(results) {
+        match result {
+            Ok(script_metrics) => metrics.extend(script_metrics),
+            Err(e) => tracing::warn!("采集脚本 {} 执行失败: {}", script.name, e),
+        }
+    }
+    metrics
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    /// 在临时目录中创建可执行的 shell 脚本
+    fn script(dir: &TempDir, name: &str, body: &str) -> CollectorScript {
+        let path = dir.path().join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        CollectorScript {
+            path,
+            name: name.to_string(),
+        }
+    }
+
+    fn metrics(entries: &[(&str, f64)]) -> HashMap<String, f64> {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn valid_output_is_merged() {
+        let dir = TempDir::new().unwrap();
+        let scripts = [
+            script(&dir, "queue", r#"echo '{"queue_depth": 12}'"#),
+            script(
+                &dir,
+                "db",
+                r#"echo '{"connections": 3, "replication_lag": 0.5}'"#,
+            ),
+        ];
+
+        assert_eq!(
+            collect_custom_metrics(&scripts).await,
+            metrics(&[
+                ("queue.queue_depth", 12.0),
+                ("db.connections", 3.0),
+                ("db.replication_lag", 0.5),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_output_is_dropped() {
+        let dir = TempDir::new().unwrap();
+        let scripts = [
+            script(&dir, "garbage", "echo 'queue_depth=12'"),
+            script(&dir, "text_value", r#"echo '{"queue_depth": "high"}'"#),
+            script(&dir, "empty", "true"),
+            script(&dir, "failed", r#"echo '{"queue_depth": 12}'; exit 1"#),
+            script(&dir, "queue", r#"echo '{"queue_depth": 12}'"#),
+        ];
+
+        assert_eq!(
+            collect_custom_metrics(&scripts).await,
+            metrics(&[("queue.queue_depth", 12.0)])
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_script_is_dropped() {
+        let dir = TempDir::new().unwrap();
+        let missing = CollectorScript {
+            path: dir.path().join("missing"),
+            name: "missing".to_string(),
+        };
+
+        assert!(collect_custom_metrics(&[missing]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn timed_out_script_is_dropped() {
+        let dir = TempDir::new().unwrap();
+        let scripts = [
+            script(&dir, "slow", r#"sleep 30; echo '{"queue_depth": 12}'"#),
+            script(&dir, "queue", r#"echo '{"queue_depth": 3}'"#),
+        ];
+
+        let start = std::time::Instant::now();
+        let result = collect_with_timeout(&scripts, Duration::from_millis(500)).await;
+        assert_eq!(result, metrics(&[("queue.queue_depth", 3.0)]));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,16 +1,31 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::time::Duration;
+use sysinfo::System;
+
+use crate::collector_script::CollectorScript;
+use crate::exec_check::ExecCheck;
+use crate::ip_addr::IpMode;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Command {
+    #[command(subcommand)]
+    pub subcommand: Option<SubCommand>,
     /// 服务器信息上报的目标地址 (URL)
-    /// 指定服务器的 URL 地址，用于将数据上报到该地址。
-    #[arg(short, long)]
-    pub url: String,
+    /// 指定服务器的 URL 地址，用于将数据上报到该地址。支持主机名、IPv4 和 IPv6 地址，
+    /// 也可以是包含协议和端口的完整地址（如 `https://example.com:50051`），此时忽略 `--port`。
+    /// 与服务端在同一主机时可以使用 `unix:///path/to/sock` 通过 Unix 域套接字连接，不使用 TLS。
+    /// 可以用逗号分隔或重复指定多个地址，启动时按顺序尝试连接，当前地址连续无法连接时切换到下一个。
+    #[arg(short, long, value_delimiter = ',', global = true)]
+    pub url: Vec<String>,
     /// 服务器信息上报的目标端口
-    /// 指定服务器的端口号，用于将数据上报到该端口。
-    #[arg(short, long)]
-    pub port: String,
+    /// 指定服务器的端口号（1-65535），用于将数据上报到该端口。
+    #[arg(short, long, global = true, value_parser = parse_port)]
+    pub port: Option<u16>,
+    /// 服务端 Unix 域套接字路径
+    /// 与服务端在同一主机时使用，等同于 `--url unix://<PATH>`，指定后忽略 `--url` 和 `--port`。
+    #[arg(long, global = true, value_name = "PATH")]
+    pub uds: Option<String>,
     // 加密上报数据的密钥
     // 用于加密在上报过程中发送到服务器的数据，以确保数据的安全性。
     // #[arg(short, long)]
@@ -20,10 +35,22 @@ pub struct Command {
     /// 如果需要周期性上报，可以设置为大于 0 的值。
     #[arg(short = 'o', long, default_value_t = 0)]
     pub host_report_interval: u64,
-    /// 主机状态信息上报的时间间隔（秒）
-    /// 指定主机状态信息的上报间隔时间，单位为秒。默认为 1 秒，表示每秒循环上报一次。
-    #[arg(short, long, default_value_t = 1)]
-    pub state_report_interval: u64,
+    /// 主机状态信息上报的时间间隔
+    /// 支持 `250ms`、`2s`、`1m` 等格式，不带单位时按秒计算。默认为 1 秒，最小为 100ms。
+    /// 间隔越短，服务端写入数据库和推送仪表盘的负载越高：每个探针每秒写入 1000 / 间隔毫秒数 条状态，
+    /// 间隔小于 200ms 时需同时调高服务端的 `PANDA_STATE_RATE_LIMIT`（默认每秒 5 次），否则上报会被限流。
+    #[arg(short, long, default_value = "1", value_parser = parse_interval)]
+    pub state_report_interval: Duration,
+    /// 每个上报间隔内的 CPU 使用率采样次数
+    /// 大于 1 时在两次上报之间均匀采样，上报平均值以平滑瞬时波动。默认为 1，即上报时采样一次。
+    /// 相邻采样至少间隔 200ms，上报间隔除以采样次数不能小于该值。
+    #[arg(long, default_value_t = 1)]
+    pub cpu_samples: u32,
+    /// 状态上报的间隔模式
+    /// `rate` 按固定频率上报，保持上报节奏，单次上报超时后会立即开始下一轮；
+    /// `delay` 每次上报完成后再等待完整的间隔，网络较慢时不会堆积上报，但实际周期会变长。
+    #[arg(long, value_enum, default_value_t = IntervalMode::Rate)]
+    pub interval_mode: IntervalMode,
     /// ip 信息上报的时间间隔（小时）
     /// 指定 ip 信息的上报间隔时间，单位为小时。默认为 0，表示仅在启动时上报一次。
     /// 如果需要周期性上报，可以设置为大于 0 的值。
@@ -34,21 +61,480 @@ pub struct Command {
     // #[arg(short = 'c', long)]
     // pub ssl_cert_path: String,
     /// 探针ID
-    #[arg(short, long)]
-    pub agent_id: u64,
+    /// 指定为 `auto` 时根据 `/etc/machine-id`（不存在时使用主机名）自动生成，同一台机器重启后保持不变。
+    /// 显式指定时优先于 `--agent-id-source`。
+    #[arg(short, long, value_parser = parse_agent_id, global = true)]
+    pub agent_id: Option<AgentId>,
+    /// 探针ID来源，未指定 `--agent-id` 时使用
+    /// `machine-id` 对 `/etc/machine-id`（不存在时使用主机名）做哈希；
+    /// `file` 对 `--agent-id-file` 中保存的 UUID 做哈希，文件不存在时自动生成，适合从同一镜像克隆的主机。
+    /// 哈希算法为 FNV-1a 64 位，对去除首尾空白后的内容计算，取低 53 位，服务端可用同样的方法复现。
+    #[arg(long, value_enum, global = true)]
+    pub agent_id_source: Option<AgentIdSource>,
+    /// `--agent-id-source file` 使用的 UUID 文件路径
+    #[arg(long, default_value = DEFAULT_AGENT_ID_FILE)]
+    pub agent_id_file: String,
+    /// 租户 ID
+    /// 服务端为多个租户托管监控时用于隔离探针，不同租户的探针 ID 互相独立，仪表盘只能看到所属租户的探针。
+    /// 不指定时属于默认租户。只能包含字母、数字、`_`、`-` 和 `.`，以字母或数字开头，最长 63 个字符。
+    /// 服务端不信任探针自行声明的租户：非默认租户要求服务端开启客户端证书认证（`--tls-client-ca`），
+    /// 且与 `--client-cert` 证书主题中的组织（O）一致，否则连接被拒绝。
+    #[arg(long, default_value = "", value_parser = parse_tenant_id, global = true)]
+    pub tenant_id: String,
+    /// 最终使用的探针ID，由 `resolve_agent_id` 填充
+    #[arg(skip)]
+    pub server_id: u64,
+    /// 自定义采集脚本，格式为 path:name，可重复指定
+    /// 脚本每个上报周期执行一次，需输出 `{"指标名": 数值}` 格式的 JSON，
+    /// 结果以 `name.指标名` 合并到状态信息的自定义指标中。
+    #[arg(long = "collector-script")]
+    pub collector_scripts: Vec<CollectorScript>,
+    /// 健康检查命令，格式为 name:command，可重复指定
+    /// 命令通过 shell 在每个上报周期执行一次，退出码和耗时随状态信息上报，超时视为失败。
+    #[arg(long = "check")]
+    pub checks: Vec<ExecCheck>,
+    /// 单个健康检查命令的超时时间（秒）
+    #[arg(long, default_value_t = 5)]
+    pub check_timeout: u64,
+    /// gRPC 请求超时时间（秒）
+    #[arg(long, default_value_t = 10, global = true)]
+    pub grpc_timeout: u64,
+    /// 每个 gRPC 连接同时进行的最大请求数
+    /// 超出的请求排队等待，防止服务端响应变慢时请求无限堆积。
+    #[arg(long, default_value_t = 256, global = true)]
+    pub concurrency_limit: usize,
+    /// 状态上报流的发送缓冲大小
+    /// 网络较慢时最多积压的状态条数，缓冲满后上报等待发送完成。
+    /// 缓冲按需增长，最坏情况下占用约 缓冲大小 × 单条状态大小 的内存；增大可以吸收短时的网络抖动，但积压的状态会延迟送达。
+    #[arg(long, default_value_t = 16)]
+    pub state_buffer: usize,
+    /// 操作失败时的重试次数，至少为 1
+    /// 作用于单次上报模式下的连接和上报；持续运行时启动连接和命令流断开后都会一直重连，不受该次数限制。
+    #[arg(long, default_value_t = 3)]
+    pub retry_attempts: u32,
+    /// 重试的基础间隔时间（秒）
+    /// 每次重试在该值与逐次翻倍的上限（不超过 `--retry-delay-max`）之间随机等待，避免大量探针同时重连。
+    #[arg(long, default_value_t = 2)]
+    pub retry_delay: u64,
+    /// 重试的最大间隔时间（秒）
+    #[arg(long, default_value_t = 30)]
+    pub retry_delay_max: u64,
+    /// 连续多少次无法连接当前服务器后切换到下一个地址，只指定一个地址时不生效
+    #[arg(long, default_value_t = 3)]
+    pub failover_after: u32,
+    /// 统计磁盘容量时排除的文件系统类型或挂载点前缀，可用逗号分隔或重复指定
+    /// 以 `/` 开头的视为挂载点前缀，其余视为文件系统类型，不区分大小写。指定后将替换默认列表。
+    /// 同一设备的多个挂载点（绑定挂载）只统计一次。
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_values_t = ["overlay", "tmpfs", "squashfs", "devtmpfs"].map(String::from)
+    )]
+    pub exclude_fs: Vec<String>,
+    /// 状态上报的最大间隔（秒）
+    /// 大于 0 时开启自适应上报：状态与上一次发送相比没有明显变化时跳过上报，
+    /// 但至少每隔该时间发送一次。建议小于服务端的离线判定时间（10 秒）。默认为 0，表示每次都上报。
+    #[arg(long, default_value_t = 0)]
+    pub max_report_gap: u64,
+    /// 自适应上报的 CPU 使用率变化阈值（百分比）
+    #[arg(long, default_value_t = 1.0)]
+    pub cpu_epsilon: f64,
+    /// 自适应上报的内存、交换空间和磁盘用量变化阈值（MB）
+    #[arg(long, default_value_t = 1.0)]
+    pub mem_epsilon: f64,
+    /// 自适应上报的网络、磁盘 IO 和交换空间换入换出速度变化阈值（字节/秒）
+    #[arg(long, default_value_t = 1024)]
+    pub speed_epsilon: u64,
+    /// 自适应上报的系统负载变化阈值
+    #[arg(long, default_value_t = 0.05)]
+    pub load_epsilon: f64,
+    /// 自适应上报的打开文件描述符数和 TCP 连接数变化阈值
+    #[arg(long, default_value_t = 10)]
+    pub count_epsilon: u64,
+    /// 用于校验服务端证书的 CA 证书路径（PEM）
+    /// 指定后使用 TLS 连接服务器。服务端开启客户端证书认证（mTLS）时，
+    /// 需要同时指定 `--ca-cert`、`--client-cert` 和 `--client-key` 三项，缺少客户端证书的连接会被服务端拒绝。
+    #[arg(long, global = true)]
+    pub ca_cert: Option<String>,
+    /// 客户端证书路径（PEM），用于服务端开启客户端证书认证（mTLS）时认证探针
+    /// 需要同时指定 `--client-key` 和 `--ca-cert`。
+    #[arg(long, global = true)]
+    pub client_cert: Option<String>,
+    /// 客户端私钥路径（PEM），与 `--client-cert` 配对使用
+    #[arg(long, global = true)]
+    pub client_key: Option<String>,
+    /// 输出状态上报耗时统计（p50/p95/p99）的间隔（秒）
+    /// 统计最近 1000 次上报。定期上报时为状态写入上报流的耗时，可反映发送积压。默认为 0，表示不输出。
+    #[arg(long, default_value_t = 0)]
+    pub print_stats: u64,
+    /// 以 info 级别输出每次状态上报的耗时
+    /// 耗时以 `elapsed_ms` 字段输出，便于日志系统提取为指标。默认关闭，此时单次上报耗时只以 debug 级别输出，可通过 `-v` 查看。
+    #[arg(long)]
+    pub report_timing: bool,
+    /// IP 地址获取方式
+    /// `public` 通过外部服务获取公网 IP，`local` 使用本机网卡地址，不发起网络请求。
+    #[arg(long, value_enum, default_value_t = IpMode::Public)]
+    pub ip_mode: IpMode,
+    /// 公网 IP 缓存时间（秒）
+    /// 有效期内复用上一次获取的公网 IP，获取失败时继续使用旧值。设置为 0 表示不缓存。仅 `--ip-mode public` 时生效。
+    #[arg(long, default_value_t = 3600)]
+    pub ip_cache_ttl: u64,
+    /// 获取公网 IP 使用的 HTTP 代理地址（如 `http://proxy.example.com:3128`）
+    /// 未指定时按 `HTTP_PROXY`、`HTTPS_PROXY` 和 `NO_PROXY` 环境变量使用代理。仅 `--ip-mode public` 时生效。
+    #[arg(long, value_name = "URL")]
+    pub ip_proxy: Option<String>,
+    /// 网络流量状态文件路径
+    /// 指定后把累计的网络总流量保存到该文件，探针或主机重启导致网卡计数归零时继续累计，
+    /// 保证上报的总流量单调递增。不指定时直接上报网卡计数。
+    #[arg(long)]
+    pub state_file: Option<String>,
+    /// 容器感知
+    /// 开启后读取 cgroup v2 限制（`memory.max`、`memory.swap.max`、`cpu.max`），
+    /// 按容器限制上报总内存、交换空间和 CPU 核心数，内存使用量取自容器，
+    /// CPU 使用率按 `cpu.stat` 的 `usage_usec` 相对配额计算。未设置限制时仍使用主机数据。
+    #[arg(long, visible_alias = "cgroup-aware")]
+    pub container_aware: bool,
+    /// 探针标签
+    /// 格式为 `key=value`，可重复指定，随主机信息上报，用于在仪表盘中分组和筛选（如 `--label role=db --label env=prod`）。
+    /// 键只能包含字母、数字、`_`、`-` 和 `.`，以字母或数字开头，最长 63 个字符；值最长 255 字节，最多 32 个标签。
+    #[arg(long = "label", value_name = "KEY=VALUE", value_parser = parse_label)]
+    pub labels: Vec<(String, String)>,
+    /// 监视的进程名称
+    /// 可重复指定，每次上报时按名称汇总同名进程的 CPU 使用率和常驻内存，进程不存在时上报为未运行。
+    /// 名称与进程名或可执行文件名完全一致时匹配（Linux 的进程名最长 15 个字符）。
+    #[arg(long = "watch-process", value_name = "NAME")]
+    pub watch_processes: Vec<String>,
+    /// 试运行模式
+    /// 只采集一次主机信息和状态信息并以 JSON 格式打印，不连接服务器。
+    /// 仍会查询公网 IP，可以配合 `--ip-mode local` 跳过。
+    #[arg(long)]
+    pub dry_run: bool,
+    /// 单次上报模式
+    /// 连接服务器后依次上报一次主机信息、状态信息和 IP 地址，然后退出，适合 cron、systemd timer 等定时任务。
+    /// 任一上报失败时以非 0 状态码退出。
+    #[arg(long, conflicts_with = "dry_run")]
+    pub once: bool,
+    /// 单次上报时只上报状态信息，不上报主机信息和 IP 地址
+    /// 适合由定时任务频繁调用、主机信息由常驻探针或其他任务上报的场景。
+    #[arg(long, requires = "once")]
+    pub skip_host: bool,
+    /// 日志详细程度，可重复指定
+    /// 默认输出 info 及以上级别；`-v` 输出探针自身的 debug 日志，包括每次上报的耗时；
+    /// `-vv` 输出探针的 trace 日志和依赖库的 debug 日志。设置了 `RUST_LOG` 环境变量时以环境变量为准。
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+}
+
+/// 状态上报的间隔模式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IntervalMode {
+    /// 固定频率：按上报开始时间计算间隔
+    #[default]
+    Rate,
+    /// 固定延迟：按上报完成时间计算间隔
+    Delay,
+}
+
+/// 探针ID来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AgentIdSource {
+    /// 使用 `/etc/machine-id`，不存在时使用主机名
+    MachineId,
+    /// 使用持久化在本地文件中的 UUID
+    File,
+}
+
+/// `--agent-id` 参数的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentId {
+    /// 根据机器 ID 或主机名自动生成
+    Auto,
+    /// 显式指定的探针 ID
+    Id(u64),
+}
+
+/// 子命令
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubCommand {
+    /// 连接服务器并持续上报（默认）
+    Run,
+    /// 测试服务器连通性
+    /// 对每个服务器地址建立 gRPC 连接并发送一次命令请求，打印结果和耗时后退出，不上报任何数据。
+    Test,
+    /// 诊断运行环境
+    /// 检查服务器 TCP 连通性、gRPC 握手、各 IP 服务的连通性以及系统信息采集，
+    /// 任一关键检查失败时以非 0 状态码退出。
+    Doctor,
 }
 
 impl Command {
+    /// 根据 `--verbose` 次数确定的日志过滤规则
+    pub fn log_filter(&self) -> &'static str {
+        match self.verbose {
+            0 => "info",
+            1 => "info,agent=debug",
+            _ => "debug,agent=trace",
+        }
+    }
+
+    /// 确定最终使用的探针ID，显式指定的 `--agent-id` 优先
+    pub fn resolve_agent_id(&mut self) -> anyhow::Result<()> {
+        self.server_id = match (self.agent_id, self.agent_id_source) {
+            (Some(AgentId::Id(agent_id)), _) => agent_id,
+            (Some(AgentId::Auto), _) | (None, Some(AgentIdSource::MachineId)) => {
+                let agent_id = derive_agent_id()?;
+                tracing::info!("自动生成探针ID: {}", agent_id);
+                agent_id
+            }
+            (None, Some(AgentIdSource::File)) => {
+                let uuid = load_or_create_uuid(&self.agent_id_file)?;
+                let agent_id = hash_agent_id(&uuid);
+                tracing::info!("根据 {} 生成探针ID: {}", self.agent_id_file, agent_id);
+                agent_id
+            }
+            (None, None) => {
+                return Err(anyhow::anyhow!("必须指定 --agent-id 或 --agent-id-source"))
+            }
+        };
+        Ok(())
+    }
+
+    /// 是否以试运行模式运行，试运行不连接服务器，也不需要探针ID
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run && matches!(self.subcommand, None | Some(SubCommand::Run))
+    }
+
     pub fn validate(&self) -> anyhow::Result<()> {
-        if self.url.is_empty() {
-            return Err(anyhow::anyhow!("URL 不能为空"));
+        // 试运行不连接服务器，未配置服务器地址的主机上也可以使用
+        match &self.uds {
+            _ if self.is_dry_run() => {}
+            Some(path) if path.trim().is_empty() => {
+                return Err(anyhow::anyhow!("Unix 域套接字路径不能为空"));
+            }
+            Some(_) => {}
+            None => {
+                if self.url.is_empty() || self.url.iter().any(|url| url.trim().is_empty()) {
+                    return Err(anyhow::anyhow!("URL 不能为空"));
+                }
+                // 所有地址都包含协议和端口时不需要 `--port`
+                if self.port.is_none() && self.url.iter().any(|url| !url.contains("://")) {
+                    return Err(anyhow::anyhow!("端口号不能为空"));
+                }
+            }
         }
-        if self.port.is_empty() {
-            return Err(anyhow::anyhow!("端口号不能为空"));
+        if self.state_report_interval < MIN_STATE_REPORT_INTERVAL {
+            return Err(anyhow::anyhow!(
+                "状态上报间隔不能小于 {}ms",
+                MIN_STATE_REPORT_INTERVAL.as_millis()
+            ));
         }
-        if self.state_report_interval == 0 {
-            return Err(anyhow::anyhow!("状态上报间隔不能为0"));
+        if self.cpu_samples == 0 {
+            return Err(anyhow::anyhow!("CPU 采样次数不能为0"));
+        }
+        if self.state_report_interval / self.cpu_samples < sysinfo::MINIMUM_CPU_UPDATE_INTERVAL {
+            return Err(anyhow::anyhow!(
+                "CPU 采样间隔不能小于 {}ms，请减少 --cpu-samples 或增大上报间隔",
+                sysinfo::MINIMUM_CPU_UPDATE_INTERVAL.as_millis()
+            ));
+        }
+        if self.check_timeout == 0 {
+            return Err(anyhow::anyhow!("健康检查超时时间不能为0"));
+        }
+        if self.failover_after == 0 {
+            return Err(anyhow::anyhow!("切换服务器地址的失败次数不能为0"));
+        }
+        if self.retry_attempts == 0 {
+            return Err(anyhow::anyhow!("重试次数不能为0"));
+        }
+        if self.concurrency_limit == 0 {
+            return Err(anyhow::anyhow!("最大并发请求数不能为0"));
+        }
+        if self.state_buffer == 0 {
+            return Err(anyhow::anyhow!("状态上报流缓冲大小不能为0"));
+        }
+        if self.cpu_epsilon < 0.0 || self.mem_epsilon < 0.0 || self.load_epsilon < 0.0 {
+            return Err(anyhow::anyhow!("自适应上报阈值不能为负数"));
+        }
+        if self.client_cert.is_some() != self.client_key.is_some() {
+            return Err(anyhow::anyhow!("客户端证书和私钥必须同时指定"));
+        }
+        if self.client_cert.is_some() && self.ca_cert.is_none() {
+            return Err(anyhow::anyhow!("使用客户端证书时必须指定 CA 证书"));
+        }
+        if self.labels.len() > common::MAX_LABELS {
+            return Err(anyhow::anyhow!(
+                "标签数量不能超过 {} 个",
+                common::MAX_LABELS
+            ));
+        }
+        #[cfg(feature = "geoip")]
+        if let Some(proxy) = &self.ip_proxy {
+            reqwest::Proxy::all(proxy)
+                .map_err(|e| anyhow::anyhow!("无效的代理地址 {}: {}", proxy, e))?;
+        }
+        if self.retry_delay_max < self.retry_delay {
+            return Err(anyhow::anyhow!("最大重试间隔不能小于基础重试间隔"));
         }
         Ok(())
     }
 }
+
+// 常量定义
+const MACHINE_ID_PATH: &str = "/etc/machine-id"; // 机器 ID 文件路径
+const AGENT_ID_MASK: u64 = (1 << 53) - 1; // 自动生成的探针 ID 限制在 53 位内，避免前端 JSON 解析丢失精度
+const DEFAULT_AGENT_ID_FILE: &str = "/var/lib/panda-agent/agent-id"; // 默认的探针 UUID 文件路径
+const MIN_STATE_REPORT_INTERVAL: Duration = Duration::from_millis(100); // 状态上报间隔下限，避免频繁采集占用 CPU
+
+/// 解析探针 ID，支持数字或 `auto`
+/// `auto` 只做标记，实际的 ID 在日志初始化后由 `resolve_agent_id` 生成
+fn parse_agent_id(s: &str) -> Result<AgentId, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(AgentId::Auto);
+    }
+    s.parse()
+        .map(AgentId::Id)
+        .map_err(|e| format!("探针ID必须为数字或 auto: {}", e))
+}
+
+/// 解析端口号，拒绝非数字、0 和超出范围的值
+fn parse_port(s: &str) -> Result<u16, String> {
+    match s.trim().parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err(format!("端口号必须为 1-65535 之间的数字: {}", s)),
+    }
+}
+
+/// 解析时间间隔，支持 `ms`、`s`、`m` 后缀，不带单位时按秒计算
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (value, unit_ms) = if let Some(value) = s.strip_suffix("ms") {
+        (value, 1)
+    } else if let Some(value) = s.strip_suffix('s') {
+        (value, 1000)
+    } else if let Some(value) = s.strip_suffix('m') {
+        (value, 60_000)
+    } else {
+        (s, 1000)
+    };
+    let value: u64 = value
+        .trim()
+        .parse()
+        .map_err(|e| format!("时间间隔格式错误，应为 250ms、2s 或 1m 等格式: {}", e))?;
+    value
+        .checked_mul(unit_ms)
+        .map(Duration::from_millis)
+        .ok_or_else(|| format!("时间间隔过大: {}", s))
+}
+
+/// 解析租户 ID 并校验格式
+fn parse_tenant_id(s: &str) -> Result<String, String> {
+    let tenant_id = s.trim();
+    common::validate_tenant_id(tenant_id)?;
+    Ok(tenant_id.to_string())
+}
+
+/// 解析 `key=value` 格式的标签并校验键名和值的长度
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("标签格式错误，应为 key=value: {}", s))?;
+    let (key, value) = (key.trim(), value.trim());
+    common::validate_label(key, value)?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// 根据机器 ID 或主机名生成稳定的探针 ID
+/// 使用 FNV-1a 64 位哈希并截取低 53 位，算法固定，不随 Rust 版本变化。
+/// 对 N 台机器，发生冲突的概率约为 N² / 2^54，一万台机器时约为 5×10⁻⁹。
+fn derive_agent_id() -> anyhow::Result<u64> {
+    let source = std::fs::read_to_string(MACHINE_ID_PATH)
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .or_else(|| System::host_name().filter(|name| !name.is_empty()))
+        .ok_or_else(|| {
+            anyhow::anyhow!("无法读取 {} 或主机名，请手动指定探针ID", MACHINE_ID_PATH)
+        })?;
+    Ok(hash_agent_id(&source))
+}
+
+/// 将标识字符串哈希为探针 ID
+fn hash_agent_id(source: &str) -> u64 {
+    fnv1a_64(source.trim().as_bytes()) & AGENT_ID_MASK
+}
+
+/// 读取持久化的 UUID，文件不存在时生成随机 UUID v4 并写入
+fn load_or_create_uuid(path: &str) -> anyhow::Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) if !content.trim().is_empty() => return Ok(content.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow::anyhow!("读取探针ID文件 {} 失败: {}", path, e)),
+    }
+
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // 版本号 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 变体
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let uuid = format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    );
+
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| anyhow::anyhow!("创建目录 {} 失败: {}", parent.display(), e))?;
+    }
+    std::fs::write(path, format!("{}\n", uuid))
+        .map_err(|e| anyhow::anyhow!("写入探针ID文件 {} 失败: {}", path, e))?;
+    Ok(uuid)
+}
+
+/// FNV-1a 64 位哈希
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_agent_id_is_resolved_after_parsing() {
+        let mut command = Command::parse_from(["agent", "-a", "auto"]);
+        assert_eq!(command.agent_id, Some(AgentId::Auto));
+        assert_eq!(command.server_id, 0);
+
+        command.resolve_agent_id().unwrap();
+        assert_eq!(command.server_id, derive_agent_id().unwrap());
+    }
+
+    #[test]
+    fn explicit_agent_id_takes_precedence() {
+        let mut command =
+            Command::parse_from(["agent", "-a", "42", "--agent-id-source", "machine-id"]);
+        command.resolve_agent_id().unwrap();
+        assert_eq!(command.server_id, 42);
+
+        assert!(Command::try_parse_from(["agent", "-a", "abc"]).is_err());
+    }
+
+    #[test]
+    fn dry_run_skips_server_checks() {
+        let command = Command::parse_from(["agent", "--dry-run"]);
+        assert!(command.is_dry_run());
+        command.validate().unwrap();
+
+        let command = Command::parse_from(["agent", "--dry-run", "doctor"]);
+        assert!(!command.is_dry_run());
+        assert!(command.validate().is_err());
+    }
+}
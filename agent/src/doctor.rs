@@ -0,0 +1,292 @@
+use std::time::Duration;
+
+use common::panda_monitor::panda_monitor_client::PandaMonitorClient;
+use common::panda_monitor::CommandType;
+use sysinfo::{CpuRefreshKind, RefreshKind, System};
+use tokio::net::TcpStream;
+use tokio::time::{self, Instant};
+use tonic::codegen::http::Uri;
+use tonic::codegen::tokio_stream::{self, StreamExt};
+
+use crate::command::Command;
+#[cfg(feature = "geoip")]
+use crate::fetch_ip::ip_service_urls;
+use crate::monitor::{build_endpoints, command_request, ServerEndpoint};
+use crate::system_info::{DiskFilter, SystemInfoCollector};
+#[cfg(feature = "geoip")]
+use crate::utils::http_util::HttpUtil;
+
+// 常量定义
+#[cfg(feature = "geoip")]
+const IP_SERVICE_TIMEOUT_SECS: u64 = 5; // IP 服务连通性检查超时时间
+const NAME_COLUMN_WIDTH: usize = 44; // 检查项名称列的显示宽度
+
+/// 单项检查结果
+struct CheckResult {
+    name: String,
+    passed: bool,
+    /// 关键检查失败时诊断不通过，非关键检查失败只提示警告
+    critical: bool,
+    detail: String,
+}
+
+impl CheckResult {
+    fn new(name: impl Into<String>, critical: bool, result: anyhow::Result<String>) -> Self {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+        Self {
+            name: name.into(),
+            passed,
+            critical,
+            detail,
+        }
+    }
+}
+
+/// 运行诊断并打印结果，任一关键检查失败时返回错误
+pub async fn run(command: &Command) -> anyhow::Result<()> {
+    let grpc_timeout = Duration::from_secs(command.grpc_timeout);
+    let endpoints = build_endpoints(command)?;
+    let mut results = Vec::new();
+    for endpoint in &endpoints {
+        // 指定了多个服务器地址时在检查项名称中注明地址
+        let suffix = if endpoints.len() > 1 {
+            format!(" {}", endpoint)
+        } else {
+            String::new()
+        };
+        let transport = if endpoint.unix_path().is_some() {
+            "套接字"
+        } else {
+            "TCP"
+        };
+        results.push(CheckResult::new(
+            format!("{} 连通性{}", transport, suffix),
+            true,
+            check_tcp(endpoint, grpc_timeout).await,
+        ));
+        results.push(CheckResult::new(
+            format!("gRPC 握手{}", suffix),
+            true,
+            check_grpc(
+                endpoint,
+                command.server_id,
+                &command.tenant_id,
+                grpc_timeout,
+            )
+            .await,
+        ));
+    }
+
+    #[cfg(feature = "geoip")]
+    {
+        match HttpUtil::new(command.ip_proxy.as_deref()) {
+            Ok(http_util) => {
+                for url in ip_service_urls() {
+                    results.push(CheckResult::new(
+                        format!("IP 服务 {}", url),
+                        false,
+                        check_ip_service(&http_util, &url).await,
+                    ));
+                }
+            }
+            Err(e) => results.push(CheckResult::new("IP 服务", false, Err(e))),
+        }
+    }
+    results.extend(check_system_info(command));
+
+    for result in &results {
+        let status = match (result.passed, result.critical) {
+            (true, _) => "通过",
+            (false, true) => "失败",
+            (false, false) => "警告",
+        };
+        // 中文字符占两列宽度，按显示宽度补齐名称列
+        let padding = NAME_COLUMN_WIDTH.saturating_sub(display_width(&result.name));
+        println!(
+            "[{}] {}{} {}",
+            status,
+            result.name,
+            " ".repeat(padding),
+            result.detail
+        );
+    }
+
+    let failed = results
+        .iter()
+        .filter(|result| result.critical && !result.passed)
+        .count();
+    if failed > 0 {
+        return Err(anyhow::anyhow!("诊断未通过，{} 项关键检查失败", failed));
+    }
+    println!("诊断通过");
+    Ok(())
+}
+
+/// 测试与各服务器的连接：建立 gRPC 连接并发送一次命令请求，打印结果和耗时
+/// 任一地址连接失败时返回错误
+pub async fn test_connection(command: &Command) -> anyhow::Result<()> {
+    let grpc_timeout = Duration::from_secs(command.grpc_timeout);
+    let endpoints = build_endpoints(command)?;
+
+    let mut failed = 0;
+    for endpoint in &endpoints {
+        let start = Instant::now();
+        let result = check_grpc(
+            endpoint,
+            command.server_id,
+            &command.tenant_id,
+            grpc_timeout,
+        )
+        .await;
+        let elapsed_ms = start.elapsed().as_millis();
+        match result {
+            Ok(_) => println!("[通过] {} 连接成功，耗时 {} ms", endpoint, elapsed_ms),
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "[失败] {} 连接失败，耗时 {} ms: {}",
+                    endpoint, elapsed_ms, e
+                );
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} 个服务器地址连接失败", failed));
+    }
+    Ok(())
+}
+
+/// 检查到服务器的 TCP 连通性
+async fn check_tcp(endpoint: &ServerEndpoint, timeout: Duration) -> anyhow::Result<String> {
+    let Some(uri) = endpoint.tcp_uri() else {
+        return check_unix_socket(endpoint, timeout).await;
+    };
+    let (host, port) = host_port(uri)?;
+    time::timeout(timeout, TcpStream::connect((host.as_str(), port)))
+        .await
+        .map_err(|_| anyhow::anyhow!("连接 {}:{} 超时", host, port))?
+        .map_err(|e| anyhow::anyhow!("连接 {}:{} 失败: {}", host, port, e))?;
+    Ok(format!("{}:{}", host, port))
+}
+
+/// 检查 Unix 域套接字能否连接
+#[cfg(unix)]
+async fn check_unix_socket(endpoint: &ServerEndpoint, timeout: Duration) -> anyhow::Result<String> {
+    let path = endpoint
+        .unix_path()
+        .ok_or_else(|| anyhow::anyhow!("{} 不是 Unix 域套接字地址", endpoint))?;
+    time::timeout(timeout, tokio::net::UnixStream::connect(path))
+        .await
+        .map_err(|_| anyhow::anyhow!("连接 {} 超时", path.display()))?
+        .map_err(|e| anyhow::anyhow!("连接 {} 失败: {}", path.display(), e))?;
+    Ok(path.display().to_string())
+}
+
+/// 非 Unix 平台不支持 Unix 域套接字，构建端点时已拒绝此类地址
+#[cfg(not(unix))]
+async fn check_unix_socket(
+    endpoint: &ServerEndpoint,
+    _timeout: Duration,
+) -> anyhow::Result<String> {
+    Err(anyhow::anyhow!(
+        "当前平台不支持 Unix 域套接字: {}",
+        endpoint
+    ))
+}
+
+/// 检查 gRPC 握手，服务器应返回命令流建立确认
+async fn check_grpc(
+    endpoint: &ServerEndpoint,
+    server_id: u64,
+    tenant_id: &str,
+    timeout: Duration,
+) -> anyhow::Result<String> {
+    let channel = endpoint.connect().await?;
+    let mut client = PandaMonitorClient::new(channel);
+
+    let request = tokio_stream::iter([command_request(server_id, tenant_id)]);
+    let mut stream = client.send_command(request).await?.into_inner();
+    let reply = time::timeout(timeout, stream.next())
+        .await
+        .map_err(|_| anyhow::anyhow!("等待服务器确认超时"))?
+        .ok_or_else(|| anyhow::anyhow!("服务器关闭了命令流"))??;
+    if reply.command_type() != CommandType::Connected {
+        return Err(anyhow::anyhow!(
+            "服务器返回了意外的命令: {}",
+            reply.command_type().as_str_name()
+        ));
+    }
+    Ok(endpoint.to_string())
+}
+
+/// 检查 IP 服务的连通性
+#[cfg(feature = "geoip")]
+async fn check_ip_service(http_util: &HttpUtil, url: &str) -> anyhow::Result<String> {
+    let status = time::timeout(
+        Duration::from_secs(IP_SERVICE_TIMEOUT_SECS),
+        http_util.get_status(url),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("请求超时"))??;
+    if !status.is_success() {
+        return Err(anyhow::anyhow!("HTTP {}", status));
+    }
+    Ok(format!("HTTP {}", status))
+}
+
+/// 检查系统信息采集
+fn check_system_info(command: &Command) -> Vec<CheckResult> {
+    let mut sys =
+        System::new_with_specifics(RefreshKind::new().with_cpu(CpuRefreshKind::everything()));
+    sys.refresh_memory();
+    let cpu = match sys.cpus().len() {
+        0 => Err(anyhow::anyhow!("无法读取 CPU 信息")),
+        cores => Ok(format!("{} 核", cores)),
+    };
+    let memory = match sys.total_memory() {
+        0 => Err(anyhow::anyhow!("无法读取内存信息")),
+        total => Ok(format!("总内存 {} MB", total / 1024 / 1024)),
+    };
+
+    let mut collector = SystemInfoCollector::new(
+        DiskFilter::new(&command.exclude_fs),
+        command.ip_mode,
+        Duration::from_secs(command.ip_cache_ttl),
+        command.container_aware,
+    );
+    collector.refresh();
+    let disk = match collector.get_disk_details().len() {
+        0 => Err(anyhow::anyhow!("没有可统计的磁盘，请检查 --exclude-fs")),
+        count => Ok(format!("{} 个分区", count)),
+    };
+
+    vec![
+        CheckResult::new("CPU 信息", true, cpu),
+        CheckResult::new("内存信息", true, memory),
+        CheckResult::new("磁盘信息", true, disk),
+    ]
+}
+
+/// 从地址中解析主机和端口，IPv6 地址会去掉方括号
+fn host_port(uri: &Uri) -> anyhow::Result<(String, u16)> {
+    let host = uri
+        .host()
+        .ok_or_else(|| anyhow::anyhow!("服务器地址缺少主机: {}", uri))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    });
+    Ok((host, port))
+}
+
+/// 计算字符串在终端中的显示宽度，非 ASCII 字符按两列计算
+fn display_width(s: &str) -> usize {
+    s.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
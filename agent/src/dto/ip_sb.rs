@@ -1,9 +1,9 @@
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct IPSB {
+pub struct IpSb {
     /// IP 地址
     pub ip: String,
     /// 国家代码
     pub country_code: String,
-}
\ No newline at end of file
+}
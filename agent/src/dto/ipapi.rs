@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct IPAPI {
+pub struct IpApi {
     pub ip: String,
     pub country_code: String,
-}
\ No newline at end of file
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct IPIP {
+pub struct IpIp {
     /// IP 地址
     pub ip: String,
     /// 地理位置信息
@@ -12,4 +12,4 @@ pub struct IPIP {
 pub struct Location {
     /// 国家代码
     pub country_code: String,
-}
\ No newline at end of file
+}
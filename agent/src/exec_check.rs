@@ -0,0 +1,92 @@
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+
+use common::panda_monitor::Check;
+use tokio::process::Command;
+use tokio::time::{self, Instant};
+
+// 常量定义
+const FAILED_EXIT_CODE: i32 = -1; // 超时、启动失败或被信号终止时上报的退出码
+
+/// 健康检查命令
+/// 只有通过 `--check` 显式指定的命令才会被执行
+#[derive(Debug, Clone)]
+pub struct ExecCheck {
+    /// 检查名称
+    pub name: String,
+    /// 通过 shell 执行的命令
+    pub command: String,
+}
+
+impl FromStr for ExecCheck {
+    type Err = String;
+
+    /// 解析 `name:command` 格式的参数，命令中可以包含 `:`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, command) = s
+            .split_once(':')
+            .ok_or_else(|| format!("健康检查格式错误，应为 name:command: {}", s))?;
+        if name.is_empty() || command.trim().is_empty() {
+            return Err(format!("健康检查名称和命令不能为空: {}", s));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            command: command.to_string(),
+        })
+    }
+}
+
+impl ExecCheck {
+    /// 执行命令并记录退出码和耗时
+    /// 超时或无法启动的命令视为失败，退出码为 -1
+    async fn run(&self, timeout: Duration) -> Check {
+        let start = Instant::now();
+        let output = shell_command(&self.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status();
+
+        let exit_code = match time::timeout(timeout, output).await {
+            Ok(Ok(status)) => status.code().unwrap_or(FAILED_EXIT_CODE),
+            Ok(Err(e)) => {
+                tracing::warn!("健康检查 {} 启动失败: {}", self.name, e);
+                FAILED_EXIT_CODE
+            }
+            Err(_) => {
+                tracing::warn!("健康检查 {} 执行超时", self.name);
+                FAILED_EXIT_CODE
+            }
+        };
+
+        Check {
+            name: self.name.clone(),
+            exit_code,
+            duration_ms: start.elapsed().as_millis() as u64,
+            success: exit_code == 0,
+        }
+    }
+}
+
+/// 构建通过系统 shell 执行命令的进程
+#[cfg(not(windows))]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+/// 构建通过系统 shell 执行命令的进程
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+/// 并发执行所有健康检查
+pub async fn run_checks(checks: &[ExecCheck], timeout: Duration) -> Vec<Check> {
+    futures::future::join_all(checks.iter().map(|check| check.run(timeout))).await
+}
@@ -1,94 +1,274 @@
 use crate::{
-    dto::{IPAPI, IPIP, IPSB},
+    dto::{IpApi, IpIp, IpSb},
+    ip_addr::GeoIp,
     utils::http_util::HttpUtil,
 };
 use anyhow::Result;
-use std::future::Future;
-use std::pin::Pin;
+use futures::future::BoxFuture;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
 use tokio::join;
+use tokio::sync::Mutex;
 
-struct IpServiceConfig {
-    v4_url: &'static str,
-    v6_url: &'static str,
-    fetch_fn: fn(&HttpUtil) -> Pin<Box<dyn Future<Output = Result<GeoIp>> + '_>>,
+// 常量定义
+const IP_SB_V4_URL: &str = "https://api-ipv4.ip.sb/geoip"; // ip.sb 的 IPv4 地址
+const IP_SB_V6_URL: &str = "https://api-ipv6.ip.sb/geoip"; // ip.sb 的 IPv6 地址
+const IPIP_URL: &str = "https://api.myip.la/en?json"; // ipip.net 的地址，IPv4 和 IPv6 相同
+const IPAPI_URL: &str = "https://ipapi.co/json"; // ipapi.co 的地址，IPv4 和 IPv6 相同
+const FETCH_RETRIES: u32 = 1; // 单个服务被限流或超时后的重试次数
+const FETCH_RETRY_DELAY: Duration = Duration::from_millis(500); // 服务未返回 Retry-After 时的重试间隔
+const MAX_LOGGED_VALUE_LEN: usize = 64; // 日志中无效地址的最大显示字符数，服务可能返回整个错误页面
+
+/// 公网 IP 获取服务
+/// 添加新的服务时，实现此 trait 并加入 `default_providers` 返回的列表，或在创建 `GeoIpCache` 时传入，
+/// 无需修改已有服务的实现
+pub trait IpProvider: Send + Sync {
+    /// 服务名称，用于日志
+    fn name(&self) -> &str;
+    /// 服务请求的地址，用于诊断连通性
+    fn urls(&self) -> Vec<&str>;
+    /// 获取公网 IPv4 和 IPv6 地址，两者都获取失败时返回错误
+    fn fetch<'a>(&'a self, http_util: &'a HttpUtil) -> BoxFuture<'a, Result<GeoIp>>;
+}
+
+/// ip.sb，IPv4 和 IPv6 使用不同的域名
+struct IpSbProvider;
+
+impl IpProvider for IpSbProvider {
+    fn name(&self) -> &str {
+        "ip.sb"
+    }
+
+    fn urls(&self) -> Vec<&str> {
+        vec![IP_SB_V4_URL, IP_SB_V6_URL]
+    }
+
+    fn fetch<'a>(&'a self, http_util: &'a HttpUtil) -> BoxFuture<'a, Result<GeoIp>> {
+        Box::pin(fetch_ip_sb(http_util))
+    }
+}
+
+/// ipip.net，分别绑定 IPv4 和 IPv6 本地地址请求同一地址
+struct IpIpProvider;
+
+impl IpProvider for IpIpProvider {
+    fn name(&self) -> &str {
+        "ipip.net"
+    }
+
+    fn urls(&self) -> Vec<&str> {
+        vec![IPIP_URL]
+    }
+
+    fn fetch<'a>(&'a self, http_util: &'a HttpUtil) -> BoxFuture<'a, Result<GeoIp>> {
+        Box::pin(fetch_ipip(http_util))
+    }
+}
+
+/// ipapi.co，分别绑定 IPv4 和 IPv6 本地地址请求同一地址
+struct IpApiProvider;
+
+impl IpProvider for IpApiProvider {
+    fn name(&self) -> &str {
+        "ipapi.co"
+    }
+
+    fn urls(&self) -> Vec<&str> {
+        vec![IPAPI_URL]
+    }
+
+    fn fetch<'a>(&'a self, http_util: &'a HttpUtil) -> BoxFuture<'a, Result<GeoIp>> {
+        Box::pin(fetch_ipapi(http_util))
+    }
 }
 
-/// 支持的 IP 服务列表
-/// 添加新的 IP 获取服务时，需要完成以下步骤：
-/// 1. 实现一个新的异步函数（例如 `fetch_new_service`），参考 `fetch_ip_sb` 的实现
-/// 2. 在此数组中添加新的配置，例如：
-///    IpServiceConfig {
-///        v4_url: "新服务的IPv4地址",
-///        v6_url: "新服务的IPv6地址",
-///        fetch_fn: |http_util| Box::pin(fetch_new_service(http_util)),
-///    }
-static IP_SERVICES: &[IpServiceConfig] = &[
-    IpServiceConfig {
-        v4_url: "https://api-ipv4.ip.sb/geoip",
-        v6_url: "https://api-ipv6.ip.sb/geoip",
-        fetch_fn: |http_util| Box::pin(fetch_ip_sb(http_util)),
-    },
-    IpServiceConfig {
-        v4_url: "https://api.myip.la/en?json",
-        v6_url: "https://api.myip.la/en?json",
-        fetch_fn: |http_util| Box::pin(fetch_ipip(http_util)),
-    },
-    IpServiceConfig {
-        v4_url: "https://ipapi.co/json",
-        v6_url: "https://ipapi.co/json",
-        fetch_fn: |http_util| Box::pin(fetch_ipapi(http_util)),
-    },
-];
-
-#[derive(Default)]
-pub struct GeoIp {
-    pub ipv4: String,
-    pub ipv6: String,
+/// 内置的公网 IP 获取服务
+pub fn default_providers() -> Vec<Box<dyn IpProvider>> {
+    vec![
+        Box::new(IpSbProvider),
+        Box::new(IpIpProvider),
+        Box::new(IpApiProvider),
+    ]
 }
 
 /// 获取 IP 地址的主函数
 /// 该函数会并发调用所有配置的 IP 获取服务，并返回第一个成功的结果
+/// 各服务返回的地址会校验是否为对应协议族的有效地址，无效的地址丢弃，两者都无效时视为该服务失败
 /// 如果所有服务都失败，则返回默认的 GeoIp 结构体
 ///
-/// 添加新的 IP 获取服务时，无需修改此函数，只需在 `IP_SERVICES` 数组中添加新配置即可
+/// 添加新的 IP 获取服务时，无需修改此函数，只需实现 `IpProvider` 并加入服务列表即可
 ///
 /// 返回值：
 /// - 成功时返回包含 IPv4 和 IPv6 地址的 GeoIp 结构体
 /// - 失败时返回默认的 GeoIp 结构体（空地址）
-pub async fn fetch_geo_ip() -> GeoIp {
-    let http_util = HttpUtil::new();
+pub async fn fetch_geo_ip(providers: &[Box<dyn IpProvider>], proxy: Option<&str>) -> GeoIp {
+    // 各服务短暂限流时重试一次，避免所有服务同时失败导致获取结果为空
+    let http_util = match HttpUtil::new(proxy) {
+        Ok(http_util) => http_util.with_retry(FETCH_RETRIES, FETCH_RETRY_DELAY),
+        Err(e) => {
+            tracing::warn!("获取公网 IP 失败: {}", e);
+            return GeoIp::default();
+        }
+    };
 
     // 创建一个 Future 列表，用于存储所有 IP 服务的获取任务
-    let futures: Vec<_> = IP_SERVICES
+    let futures: Vec<_> = providers
         .iter()
-        .map(|config| fetch_from_service(&http_util, config))
+        .map(|provider| provider.fetch(&http_util))
         .collect();
 
     // 并发执行所有任务
     let results = futures::future::join_all(futures).await;
 
     // 返回第一个成功的结果
-    for result in results {
-        if result.is_ok() {
-            return result.unwrap();
+    results
+        .into_iter()
+        .zip(providers)
+        .find_map(|(result, provider)| {
+            match result.and_then(|geo_ip| validate_geo_ip(geo_ip, provider.name())) {
+                Ok(geo_ip) => Some(geo_ip),
+                Err(e) => {
+                    tracing::debug!("{} 获取公网 IP 失败: {}", provider.name(), e);
+                    None
+                }
+            }
+        })
+        .unwrap_or_default()
+}
+
+/// 校验服务返回的 IPv4 和 IPv6 地址，两者都无效时返回错误
+fn validate_geo_ip(geo_ip: GeoIp, provider: &str) -> Result<GeoIp> {
+    let geo_ip = GeoIp {
+        ipv4: validate_ip(&geo_ip.ipv4, false, provider),
+        ipv6: validate_ip(&geo_ip.ipv6, true, provider),
+    };
+    if geo_ip.ipv4.is_empty() && geo_ip.ipv6.is_empty() {
+        return Err(anyhow::anyhow!("未返回有效的 IP 地址"));
+    }
+    Ok(geo_ip)
+}
+
+/// 校验单个地址，返回规范格式的地址
+/// 不是有效地址或协议族不符（如 IPv4 地址的服务返回了 IPv6 地址）时记录日志并返回空字符串
+fn validate_ip(value: &str, ipv6: bool, provider: &str) -> String {
+    let family = if ipv6 { "IPv6" } else { "IPv4" };
+    let value = value.trim();
+    if value.is_empty() {
+        return String::new();
+    }
+    match value.parse::<IpAddr>() {
+        Ok(addr) if addr.is_ipv6() == ipv6 => addr.to_string(),
+        Ok(addr) => {
+            tracing::warn!(
+                "{} 返回的 {} 地址协议族不符，已丢弃: {}",
+                provider,
+                family,
+                addr
+            );
+            String::new()
+        }
+        Err(_) => {
+            let shown: String = value.chars().take(MAX_LOGGED_VALUE_LEN).collect();
+            tracing::warn!(
+                "{} 返回了无效的 {} 地址，已丢弃: {:?}",
+                provider,
+                family,
+                shown
+            );
+            String::new()
+        }
+    }
+}
+
+/// 公网 IP 缓存
+/// 在有效期内复用上一次成功获取的结果，避免频繁请求外部服务被限流；
+/// 过期后重新获取，获取失败时继续使用旧值
+pub struct GeoIpCache {
+    ttl: Duration,
+    /// 依次尝试的公网 IP 获取服务
+    providers: Vec<Box<dyn IpProvider>>,
+    /// 请求 IP 服务使用的代理地址，为空时按环境变量使用代理
+    proxy: Option<String>,
+    /// 上一次成功获取的结果及获取时间
+    cached: Mutex<Option<(GeoIp, Instant)>>,
+}
+
+impl std::fmt::Debug for GeoIpCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let providers = self.providers.iter().map(|p| p.name()).collect::<Vec<_>>();
+        f.debug_struct("GeoIpCache")
+            .field("ttl", &self.ttl)
+            .field("providers", &providers)
+            .field("proxy", &self.proxy)
+            .field("cached", &self.cached)
+            .finish()
+    }
+}
+
+impl GeoIpCache {
+    /// 创建缓存，`ttl` 为 0 时不缓存，每次都重新获取
+    pub fn new(ttl: Duration, providers: Vec<Box<dyn IpProvider>>) -> Self {
+        Self {
+            ttl,
+            providers,
+            proxy: None,
+            cached: Mutex::new(None),
         }
     }
 
-    GeoIp::default()
+    /// 设置请求 IP 服务使用的代理地址
+    pub fn with_proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// 获取公网 IP，缓存未过期时直接返回缓存
+    pub async fn get(&self) -> GeoIp {
+        // 获取期间持有锁，避免并发调用重复请求外部服务
+        let mut cached = self.cached.lock().await;
+        if let Some((geo_ip, fetched_at)) = cached.as_ref() {
+            if fetched_at.elapsed() < self.ttl {
+                return geo_ip.clone();
+            }
+        }
+
+        let geo_ip = fetch_geo_ip(&self.providers, self.proxy.as_deref()).await;
+        if geo_ip.ipv4.is_empty() && geo_ip.ipv6.is_empty() {
+            return match cached.as_ref() {
+                Some((stale, _)) => {
+                    tracing::warn!("获取公网 IP 失败，继续使用缓存的结果");
+                    stale.clone()
+                }
+                None => geo_ip,
+            };
+        }
+        *cached = Some((geo_ip.clone(), Instant::now()));
+        geo_ip
+    }
 }
 
-async fn fetch_from_service(http_util: &HttpUtil, config: &IpServiceConfig) -> Result<GeoIp> {
-    (config.fetch_fn)(http_util).await
+/// 获取所有内置 IP 服务的地址，用于诊断连通性
+pub fn ip_service_urls() -> Vec<String> {
+    default_providers()
+        .iter()
+        .flat_map(|provider| {
+            provider
+                .urls()
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .collect()
 }
 
 async fn fetch_ip_sb(http_util: &HttpUtil) -> Result<GeoIp> {
     let ipv4 = http_util
-        .send_get::<IPSB>(IP_SERVICES[0].v4_url)
+        .send_get::<IpSb>(IP_SB_V4_URL)
         .await
         .unwrap_or_default();
 
     let ipv6 = http_util
-        .send_get::<IPSB>(IP_SERVICES[0].v6_url)
+        .send_get::<IpSb>(IP_SB_V6_URL)
         .await
         .unwrap_or_default();
 
@@ -105,8 +285,8 @@ async fn fetch_ip_sb(http_util: &HttpUtil) -> Result<GeoIp> {
 }
 
 async fn fetch_ipip(http_util: &HttpUtil) -> Result<GeoIp> {
-    let ipv4 = http_util.send_get_on_ipv4::<IPIP>(IP_SERVICES[1].v4_url);
-    let ipv6 = http_util.send_get_on_ipv6::<IPIP>(IP_SERVICES[1].v6_url);
+    let ipv4 = http_util.send_get_on_ipv4::<IpIp>(IPIP_URL);
+    let ipv6 = http_util.send_get_on_ipv6::<IpIp>(IPIP_URL);
     let (ipv4, ipv6) = join!(ipv4, ipv6);
 
     let ipv4 = ipv4.unwrap_or_default();
@@ -126,12 +306,12 @@ async fn fetch_ipip(http_util: &HttpUtil) -> Result<GeoIp> {
 
 async fn fetch_ipapi(http_util: &HttpUtil) -> Result<GeoIp> {
     let ipv4 = http_util
-        .send_get_on_ipv4::<IPAPI>(IP_SERVICES[2].v4_url)
+        .send_get_on_ipv4::<IpApi>(IPAPI_URL)
         .await
         .unwrap_or_default();
 
     let ipv6 = http_util
-        .send_get_on_ipv6::<IPAPI>(IP_SERVICES[2].v6_url)
+        .send_get_on_ipv6::<IpApi>(IPAPI_URL)
         .await
         .unwrap_or_default();
 
@@ -0,0 +1,73 @@
+use common::panda_monitor::GpuInfo;
+
+/// NVIDIA GPU 信息收集器
+/// 开启 `gpu-nvidia` 特性时通过 NVML 采集，NVML 只在创建时加载一次；
+/// 未开启特性、未安装驱动或没有 GPU 时不采集，返回空列表
+#[derive(Debug)]
+pub struct GpuCollector {
+    #[cfg(feature = "gpu-nvidia")]
+    nvml: Option<nvml_wrapper::Nvml>,
+}
+
+impl GpuCollector {
+    #[cfg(feature = "gpu-nvidia")]
+    pub fn new() -> Self {
+        let nvml = match nvml_wrapper::Nvml::init() {
+            Ok(nvml) => Some(nvml),
+            Err(e) => {
+                tracing::info!("NVML 初始化失败，不采集 GPU 信息: {}", e);
+                None
+            }
+        };
+        Self { nvml }
+    }
+
+    #[cfg(not(feature = "gpu-nvidia"))]
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// 获取各 GPU 的使用率、显存和温度，按设备序号排序
+    /// 单个 GPU 读取失败时跳过该 GPU，不支持的单项指标为 0
+    #[cfg(feature = "gpu-nvidia")]
+    pub fn collect(&self) -> Vec<GpuInfo> {
+        use nvml_wrapper::enum_wrappers::device::TemperatureSensor;
+
+        let Some(nvml) = &self.nvml else {
+            return Vec::new();
+        };
+        let count = match nvml.device_count() {
+            Ok(count) => count,
+            Err(e) => {
+                tracing::debug!("读取 GPU 数量失败: {}", e);
+                return Vec::new();
+            }
+        };
+        (0..count)
+            .filter_map(|index| match nvml.device_by_index(index) {
+                Ok(device) => {
+                    let memory = device.memory_info().ok();
+                    Some(GpuInfo {
+                        index,
+                        name: device.name().unwrap_or_default(),
+                        utilization: device.utilization_rates().map_or(0, |rates| rates.gpu),
+                        mem_used: memory.as_ref().map_or(0, |memory| memory.used),
+                        mem_total: memory.as_ref().map_or(0, |memory| memory.total),
+                        temperature: device
+                            .temperature(TemperatureSensor::Gpu)
+                            .unwrap_or_default(),
+                    })
+                }
+                Err(e) => {
+                    tracing::debug!("读取 GPU {} 失败: {}", index, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    #[cfg(not(feature = "gpu-nvidia"))]
+    pub fn collect(&self) -> Vec<GpuInfo> {
+        Vec::new()
+    }
+}
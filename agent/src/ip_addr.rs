@@ -0,0 +1,53 @@
+use std::net::IpAddr;
+
+use sysinfo::Networks;
+
+/// IP 地址获取方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IpMode {
+    /// 通过外部服务获取公网 IP，未开启 `geoip` 特性时上报空地址
+    #[default]
+    Public,
+    /// 使用本机网卡上的地址，不发起网络请求
+    Local,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GeoIp {
+    pub ipv4: String,
+    pub ipv6: String,
+}
+
+/// 从本机网卡获取 IP 地址
+/// 跳过回环地址和链路本地地址，按网卡名称排序后取第一个 IPv4 和 IPv6 地址
+pub fn local_ip(networks: &Networks) -> GeoIp {
+    let mut interfaces = networks.list().iter().collect::<Vec<_>>();
+    interfaces.sort_by_key(|(name, _)| name.as_str());
+
+    let addrs = interfaces
+        .into_iter()
+        .flat_map(|(_, data)| data.ip_networks())
+        .map(|network| network.addr)
+        .filter(|addr| !addr.is_loopback() && !is_link_local(addr))
+        .collect::<Vec<_>>();
+    GeoIp {
+        ipv4: addrs
+            .iter()
+            .find(|addr| addr.is_ipv4())
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        ipv6: addrs
+            .iter()
+            .find(|addr| addr.is_ipv6())
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+    }
+}
+
+/// 判断是否为链路本地地址（169.254.0.0/16 或 fe80::/10）
+fn is_link_local(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_link_local(),
+        IpAddr::V6(addr) => (addr.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// 常量定义
+const WINDOW_SIZE: usize = 1000; // 统计窗口内保留的最大样本数
+
+/// 状态上报耗时统计
+/// 保留最近 `WINDOW_SIZE` 次上报的耗时，按 `print_interval` 周期输出分位数
+#[derive(Debug)]
+pub struct LatencyStats {
+    samples: VecDeque<Duration>,
+    /// 输出统计的间隔，为 0 时不输出
+    print_interval: Duration,
+    last_print: Instant,
+}
+
+impl LatencyStats {
+    pub fn new(print_interval: Duration) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(WINDOW_SIZE),
+            print_interval,
+            last_print: Instant::now(),
+        }
+    }
+
+    /// 记录一次上报耗时
+    pub fn record(&mut self, elapsed: Duration) {
+        if self.samples.len() == WINDOW_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(elapsed);
+    }
+
+    /// 到达输出间隔时输出耗时分位数
+    pub fn print_if_due(&mut self, server_id: u64) {
+        if self.print_interval.is_zero() || self.last_print.elapsed() < self.print_interval {
+            return;
+        }
+        self.last_print = Instant::now();
+
+        let mut sorted = self.samples.iter().copied().collect::<Vec<_>>();
+        if sorted.is_empty() {
+            return;
+        }
+        sorted.sort_unstable();
+        tracing::info!(
+            server_id,
+            samples = sorted.len(),
+            p50_ms = percentile(&sorted, 50).as_secs_f64() * 1000.0,
+            p95_ms = percentile(&sorted, 95).as_secs_f64() * 1000.0,
+            p99_ms = percentile(&sorted, 99).as_secs_f64() * 1000.0,
+            "状态上报耗时统计"
+        );
+    }
+}
+
+/// 计算已排序样本的分位数（最近秩法）
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
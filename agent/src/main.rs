@@ -1,28 +1,67 @@
 use clap::Parser;
-use command::Command;
+use command::{Command, SubCommand};
 use monitor::ServerMonitorAgent;
+use tracing_subscriber::EnvFilter;
 
+mod collector_script;
 mod command;
+mod doctor;
+#[cfg(feature = "geoip")]
 mod dto;
+mod exec_check;
+#[cfg(feature = "geoip")]
 mod fetch_ip;
+mod gpu;
+mod ip_addr;
+mod latency_stats;
 mod monitor;
-mod utils;
+mod net_transfer;
+mod report_filter;
 mod system_info;
+mod utils;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let command = Command::parse();
+    let mut command = Command::parse();
+
+    // 初始化日志，日志级别由 RUST_LOG 控制，未设置时按 `--verbose` 次数确定
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(command.log_filter())),
+        )
+        .init();
+
     command.validate()?;
-    
-    match ServerMonitorAgent::new(command).await {
-        Ok(mut agent) => {
-            match agent.send_command().await {
-                Ok(_) => println!("命令执行成功"),
-                Err(e) => eprintln!("命令执行失败: {}", e)
-            }
-        },
-        Err(e) => eprintln!("创建代理实例失败: {}", e)
+
+    // 试运行模式下只打印采集结果，不连接服务器，也不需要探针ID
+    if command.is_dry_run() {
+        return ServerMonitorAgent::dry_run(command).await;
+    }
+
+    command.resolve_agent_id()?;
+
+    match command.subcommand {
+        Some(SubCommand::Doctor) => return doctor::run(&command).await,
+        Some(SubCommand::Test) => return doctor::test_connection(&command).await,
+        Some(SubCommand::Run) | None => {}
+    }
+
+    // 单次上报模式下上报失败时以非 0 状态码退出
+    if command.once {
+        let with_host = !command.skip_host;
+        return ServerMonitorAgent::new(command)
+            .await?
+            .report_once(with_host)
+            .await;
     }
-    
+
+    // 持续运行，启动时连接失败和命令流断开后都会自动重连
+    // 配置错误等无法通过重试恢复的错误直接返回，以非 0 状态码退出
+    let mut agent = ServerMonitorAgent::new_persistent(command)
+        .await
+        .map_err(|e| anyhow::anyhow!("创建代理实例失败: {}", e))?;
+    agent.run().await;
+
     Ok(())
 }
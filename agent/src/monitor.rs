@@ -1,121 +1,307 @@
-use crate::fetch_ip::fetch_geo_ip;
-use crate::{command::Command, system_info::SystemInfoCollector};
+use crate::collector_script::{collect_custom_metrics, CollectorScript};
+use crate::exec_check::{run_checks, ExecCheck};
+use crate::latency_stats::LatencyStats;
+use crate::report_filter::ReportFilter;
+use crate::utils::backoff::Backoff;
+use crate::{
+    command::{Command, IntervalMode},
+    system_info::{DiskFilter, Snapshot, SystemInfoCollector},
+};
 use common::panda_monitor::{
-    panda_monitor_client::PandaMonitorClient, AgentInfo, CommandRequest, Host, HostRequest, State,
-    StateRequest, UpdateIpRequest,
+    panda_monitor_client::PandaMonitorClient, AgentInfo, CommandAck, CommandRequest, CommandType,
+    Host, HostRequest, ServerResponse, State, StateRequest, UpdateIpRequest,
 };
+use common::version::Version;
+use futures::FutureExt;
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::path::PathBuf;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio::time;
 use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
-use tonic::codegen::tokio_stream::StreamExt;
-use tonic::transport::Channel;
-use tonic::Status;
+use tonic::codegen::tokio_stream::{self, StreamExt};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Code, Request, Response, Status, Streaming};
 
 // 常量定义
-const VERSION: &'static str = include_str!(concat!(env!("OUT_DIR"), "/VERSION"));
-const GRPC_TIMEOUT_SECS: u64 = 10; // gRPC请求超时时间
-const RETRY_ATTEMPTS: u32 = 3; // 操作重试次数
-const RETRY_DELAY_SECS: u64 = 2; // 重试间隔时间
+const VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/VERSION"));
+const COMMAND_REQUEST_BUFFER: usize = 16; // 命令请求流的发送缓冲大小，首个请求之后只发送命令确认，缓冲满时丢弃确认
+const UNIX_SCHEME: &str = "unix://"; // Unix 域套接字地址的前缀
+const UNIX_ENDPOINT_PLACEHOLDER: &str = "http://localhost"; // Unix 域套接字连接使用的占位地址
+const BOOT_TIME_TOLERANCE_SECS: u64 = 10; // 开机时间的变化不超过该值时视为时钟调整，而不是重启
+const MIN_REMOTE_INTERVAL: Duration = Duration::from_secs(1); // 远程调整上报间隔的下限
+const MAX_REMOTE_INTERVAL: Duration = Duration::from_secs(600); // 远程调整上报间隔的上限，上报期间的命令在每轮上报前处理，过长会延迟停止命令
+
+/// 持续发送状态的上报流
+#[derive(Debug)]
+struct StateStream {
+    tx: mpsc::Sender<StateRequest>,
+    /// 上报请求任务，流结束后得到服务端的响应
+    response: JoinHandle<Result<Response<ServerResponse>, Status>>,
+}
 
 /// 服务器监控代理
 #[derive(Debug)]
 pub struct ServerMonitorAgent {
-    client: PandaMonitorClient<Channel>, // gRPC客户端
-    server_id: u64,                      // 服务器ID
-    system_info: SystemInfoCollector,    // 系统信息收集器
-    report_state: bool,                  // 是否上报状态
+    client: PandaMonitorClient<Channel>,              // gRPC客户端
+    endpoints: Vec<ServerEndpoint>,                   // 所有服务器地址，按优先级排列
+    current_endpoint: usize,                          // 当前连接的服务器地址下标
+    consecutive_failures: u32,                        // 当前服务器连续无法连接的次数
+    failover_after: u32,                              // 连续无法连接多少次后切换服务器地址
+    server_id: u64,                                   // 服务器ID
+    tenant_id: String,                                // 租户ID，为空时属于默认租户
+    system_info: SystemInfoCollector,                 // 系统信息收集器
+    report_state: bool,                               // 是否上报状态
+    collector_scripts: Vec<CollectorScript>,          // 自定义采集脚本
+    checks: Vec<ExecCheck>,                           // 健康检查命令
+    check_timeout: Duration,                          // 健康检查超时时间
+    retry_attempts: u32,                              // 操作重试次数
+    backoff: Backoff,                                 // 重试退避策略
+    report_filter: ReportFilter,                      // 自适应上报过滤器
+    version_warned: bool,                             // 是否已提示探针版本过低
+    latency_stats: LatencyStats,                      // 状态上报耗时统计
+    report_timing: bool,                              // 是否以 info 级别输出每次上报耗时
+    report_interval: Duration,                        // 状态上报间隔
+    default_report_interval: Duration,                // 启动参数指定的状态上报间隔，停止上报时恢复
+    interval_mode: IntervalMode,                      // 状态上报间隔模式
+    cpu_samples: u32,                                 // 每个上报间隔内的 CPU 使用率采样次数
+    host_report_interval: Duration,                   // 主机信息上报间隔，为 0 时仅在启动时上报一次
+    last_host_report: Option<time::Instant>,          // 上一次定期上报主机信息的时间
+    state_stream: Option<StateStream>,                // 正在使用的状态上报流
+    command_tx: Option<mpsc::Sender<CommandRequest>>, // 命令流的请求发送端，用于发送命令确认
+    grpc_timeout: Duration,                           // 单次请求超时时间，不作用于状态上报流
+    state_buffer: usize,                              // 状态上报流的发送缓冲大小
+    last_boot_time: Option<u64>,                      // 上一次状态上报时的主机开机时间
+    rebooted: bool,                                   // 检测到重启后尚未成功上报
 }
 
 impl ServerMonitorAgent {
-    /// 创建新的监控代理实例
+    /// 创建新的监控代理实例，连接服务器失败时最多重试 `--retry-attempts` 次
     pub async fn new(command: Command) -> anyhow::Result<Self> {
-        let url = format!("grpc://{}:{}", command.url, command.port);
+        Self::create(command, false).await
+    }
+
+    /// 创建持续运行的监控代理实例，与 `run` 一样按退避间隔无限重试连接服务器，
+    /// 探针先于服务端启动时等待服务端就绪，而不是直接退出
+    pub async fn new_persistent(command: Command) -> anyhow::Result<Self> {
+        Self::create(command, true).await
+    }
 
-        // 添加连接重试机制
+    /// `persistent` 为 true 时连接服务器不限重试次数
+    async fn create(command: Command, persistent: bool) -> anyhow::Result<Self> {
+        let endpoints = build_endpoints(&command)?;
+        let retry_attempts = command.retry_attempts;
+        let report_filter = ReportFilter::new(&command);
+        let backoff = Backoff::new(
+            Duration::from_secs(command.retry_delay),
+            Duration::from_secs(command.retry_delay_max),
+        );
+        let mut system_info = SystemInfoCollector::new(
+            DiskFilter::new(&command.exclude_fs),
+            command.ip_mode,
+            Duration::from_secs(command.ip_cache_ttl),
+            command.container_aware,
+        )
+        .with_labels(command.labels.iter().cloned().collect())
+        .with_watched_processes(command.watch_processes.clone())
+        .with_ip_proxy(command.ip_proxy.clone());
+        if let Some(state_file) = &command.state_file {
+            system_info = system_info.with_transfer_state(state_file)?;
+        }
+
+        // 添加连接重试机制，每次重试按顺序尝试所有地址
         let mut attempts = 0;
-        let channel = loop {
-            match Channel::from_shared(url.clone())?
-                .timeout(Duration::from_secs(GRPC_TIMEOUT_SECS))
-                .connect_timeout(Duration::from_secs(GRPC_TIMEOUT_SECS))
-                .concurrency_limit(256)
-                .connect()
-                .await
-            {
-                Ok(channel) => break channel,
+        let (current_endpoint, channel) = loop {
+            match connect_any(&endpoints, 0).await {
+                Ok(connected) => break connected,
                 Err(e) => {
                     attempts += 1;
-                    if attempts >= RETRY_ATTEMPTS {
+                    if persistent {
+                        tracing::warn!("连接失败，正在重试 (第 {} 次): {}", attempts, e);
+                    } else if attempts >= retry_attempts {
                         return Err(anyhow::anyhow!(
                             "连接服务器失败，已重试 {} 次: {}",
-                            RETRY_ATTEMPTS,
+                            retry_attempts,
                             e
                         ));
+                    } else {
+                        tracing::warn!(
+                            "连接失败，正在重试 ({}/{}): {}",
+                            attempts,
+                            retry_attempts,
+                            e
+                        );
                     }
-                    println!(
-                        "连接失败，正在重试 ({}/{}): {}",
-                        attempts, RETRY_ATTEMPTS, e
-                    );
-                    time::sleep(Duration::from_secs(RETRY_DELAY_SECS)).await;
+                    time::sleep(backoff.delay(attempts)).await;
                 }
             }
         };
 
         Ok(Self {
             client: PandaMonitorClient::new(channel),
-            server_id: command.agent_id,
-            system_info: SystemInfoCollector::new(),
+            endpoints,
+            current_endpoint,
+            consecutive_failures: 0,
+            failover_after: command.failover_after,
+            server_id: command.server_id,
+            tenant_id: command.tenant_id.clone(),
+            system_info,
             report_state: false,
+            collector_scripts: command.collector_scripts,
+            checks: command.checks,
+            check_timeout: Duration::from_secs(command.check_timeout),
+            retry_attempts,
+            backoff,
+            report_filter,
+            version_warned: false,
+            latency_stats: LatencyStats::new(Duration::from_secs(command.print_stats)),
+            report_timing: command.report_timing,
+            report_interval: command.state_report_interval,
+            default_report_interval: command.state_report_interval,
+            interval_mode: command.interval_mode,
+            cpu_samples: command.cpu_samples,
+            host_report_interval: Duration::from_secs(command.host_report_interval),
+            last_host_report: None,
+            state_stream: None,
+            command_tx: None,
+            grpc_timeout: Duration::from_secs(command.grpc_timeout),
+            state_buffer: command.state_buffer,
+            last_boot_time: None,
+            rebooted: false,
         })
     }
 
-    /// 发送命令并处理响应
-    pub async fn send_command(&mut self) -> anyhow::Result<()> {
-        let mut attempts = 0;
+    /// 试运行：采集一次主机信息和状态信息并打印，不连接服务器
+    pub async fn dry_run(command: Command) -> anyhow::Result<()> {
+        let mut system_info = SystemInfoCollector::new(
+            DiskFilter::new(&command.exclude_fs),
+            command.ip_mode,
+            Duration::from_secs(command.ip_cache_ttl),
+            command.container_aware,
+        )
+        .with_labels(command.labels.iter().cloned().collect())
+        .with_watched_processes(command.watch_processes.clone())
+        .with_ip_proxy(command.ip_proxy.clone());
+        // CPU 使用率和磁盘 IO 速度需要两次采样才能计算
+        system_info.refresh();
+        time::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL).await;
 
-        while attempts < RETRY_ATTEMPTS {
-            match self.try_send_command().await {
-                Ok(_) => return Ok(()),
-                Err(e) => {
-                    attempts += 1;
-                    if attempts == RETRY_ATTEMPTS {
-                        return Err(anyhow::anyhow!(
-                            "发送命令失败，已重试 {} 次: {}",
-                            RETRY_ATTEMPTS,
-                            e
-                        )
-                        .into());
-                    }
-                    println!(
-                        "发送命令失败，正在重试 ({}/{}): {}",
-                        attempts, RETRY_ATTEMPTS, e
-                    );
-                    time::sleep(Duration::from_secs(RETRY_DELAY_SECS)).await;
-                }
+        let Snapshot { host, mut state } = system_info.snapshot().await;
+        (state.custom_metrics, state.checks) = tokio::join!(
+            collect_custom_metrics(&command.collector_scripts),
+            run_checks(&command.checks, Duration::from_secs(command.check_timeout))
+        );
+
+        let output = serde_json::json!({ "host": host, "state": state });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        Ok(())
+    }
+
+    /// 依次上报一次主机信息、状态信息和 IP 地址
+    /// 某项失败时继续上报其余各项，最后汇总返回所有失败原因
+    /// `with_host` 为 false 时只上报状态信息
+    pub async fn report_once(&mut self, with_host: bool) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+        if with_host {
+            if let Err(e) = self.report_server_host().await {
+                errors.push(format!("主机信息上报失败: {}", e));
+            }
+        } else {
+            self.refresh_system_components();
+        }
+        // CPU 使用率需要两次采样才能计算，上面已采样一次
+        time::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL).await;
+        if let Err(e) = self.report_server_state(true).await {
+            errors.push(e.to_string());
+        }
+        if with_host {
+            if let Err(e) = self.update_ip().await {
+                errors.push(format!("IP 上报失败: {}", e));
             }
         }
 
-        Ok(())
+        if errors.is_empty() {
+            tracing::info!("单次上报完成");
+            return Ok(());
+        }
+        Err(anyhow::anyhow!(errors.join("; ")))
     }
 
-    /// 尝试发送单个命令
-    async fn try_send_command(&mut self) -> anyhow::Result<()> {
+    /// 持续运行命令会话，直到进程退出
+    /// 命令流结束（如服务端重启、命令流超时）或出错后按退避间隔无限重连，
+    /// 成功建立命令流后重新从基础间隔开始退避，服务端重启后探针无需外部守护进程即可恢复
+    pub async fn run(&mut self) {
+        let mut attempts = 0;
+        loop {
+            let result = match self.open_command_stream().await {
+                Ok(mut stream) => {
+                    attempts = 0;
+                    self.receive_commands(&mut stream).await
+                }
+                Err(e) => Err(e),
+            };
+            self.command_tx = None;
+            attempts += 1;
+            match result {
+                Ok(()) => tracing::warn!("命令流已结束，正在重新连接 (第 {} 次)", attempts),
+                Err(e) => tracing::warn!("命令会话中断，正在重新连接 (第 {} 次): {}", attempts, e),
+            }
+            self.failover_if_needed().await;
+            time::sleep(self.backoff.delay(attempts)).await;
+        }
+    }
+
+    /// 发送命令请求，建立接收命令的流
+    async fn open_command_stream(
+        &mut self,
+    ) -> anyhow::Result<Streaming<common::panda_monitor::Command>> {
         let mut client = self.client.clone();
-        let (tx, rx) = mpsc::channel(128);
+        let (tx, rx) = mpsc::channel(COMMAND_REQUEST_BUFFER);
 
         let command_request = self.create_command_request();
         tx.send(command_request)
             .await
             .map_err(|e| anyhow::anyhow!("发送命令请求失败: {}", e))?;
 
-        let mut stream = client
-            .send_command(ReceiverStream::new(rx))
-            .await
-            .map_err(|e| anyhow::anyhow!("创建命令流失败: {}", e))?
-            .into_inner();
+        let request = self.timed_request(ReceiverStream::new(rx));
+        match client.send_command(request).await {
+            Ok(response) => {
+                self.consecutive_failures = 0;
+                self.command_tx = Some(tx);
+                tracing::info!("命令流已建立");
+                Ok(response.into_inner())
+            }
+            Err(e) => {
+                self.record_failure(&e);
+                Err(anyhow::anyhow!("创建命令流失败: {}", e))
+            }
+        }
+    }
 
-        while let Some(result) = stream.next().await {
-            self.parse_command(result).await?;
+    /// 接收并处理命令，命令流正常结束时返回 Ok
+    async fn receive_commands(
+        &mut self,
+        stream: &mut Streaming<common::panda_monitor::Command>,
+    ) -> anyhow::Result<()> {
+        loop {
+            // 等待命令的同时按间隔上报主机信息
+            let next_host_report = self.next_host_report_in();
+            tokio::select! {
+                result = stream.next() => match result {
+                    Some(result) => {
+                        self.parse_command(result).await?;
+                        if self.report_state {
+                            self.start_reporting_state(stream).await?;
+                        }
+                    }
+                    None => break,
+                },
+                _ = time::sleep(next_host_report.unwrap_or_default()), if next_host_report.is_some() => {
+                    self.report_host_if_due().await;
+                }
+            }
         }
 
         Ok(())
@@ -133,51 +319,166 @@ impl ServerMonitorAgent {
             return Ok(()); // ID不匹配时忽略命令
         }
 
-        match command.data.as_str() {
-            "stop_report_state" => {
-                self.shutdown().await?;
+        let command_type = command.command_type();
+        let success = match command_type {
+            CommandType::StopReportState | CommandType::Offline => {
+                let result = self.shutdown().await;
+                // 远程调整的上报间隔只在本次上报期间有效，停止失败时同样恢复
+                self.report_interval = self.default_report_interval;
+                self.send_ack(command_type, result.is_ok());
+                return result;
             }
-            "report_state" => {
+            // 在命令流循环中开始上报，上报期间再次收到时忽略
+            CommandType::ReportState => {
                 self.report_state = true;
-                self.start_reporting_state().await?;
+                true
             }
-            "report_host" => {
-                self.refresh_system_components();
-                self.create_host_request().await;
+            CommandType::ReportOnce => match self.report_server_state(true).await {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::error!("单次状态上报失败: {}", e);
+                    false
+                }
+            },
+            CommandType::ReportHost => match self.report_server_host().await {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::error!("主机信息上报失败: {}", e);
+                    false
+                }
+            },
+            CommandType::ReportIp => match self.update_ip().await {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::error!("IP 上报失败: {}", e);
+                    false
+                }
+            },
+            CommandType::SetInterval => match command.interval_secs() {
+                Some(secs) => {
+                    self.set_report_interval(secs);
+                    true
+                }
+                None => {
+                    tracing::warn!("无效的上报间隔命令: {}", command.data);
+                    false
+                }
+            },
+            // 广播和连接维护类命令不需要确认
+            CommandType::StateUpdate | CommandType::Connected | CommandType::Heartbeat => {
+                return Ok(())
             }
-            "report_ip" => {
-                self.create_update_ip_request().await;
+            // 较新版本服务端新增的命令类型也会解析为未指定，输出原始值便于排查版本不一致
+            CommandType::Unspecified => {
+                tracing::warn!("未知命令: {} (类型 {})", command.data, command.command);
+                false
             }
-            _ => println!("未知命令: {}", command.data),
-        }
+        };
+        self.send_ack(command_type, success);
 
         Ok(())
     }
 
+    /// 在命令流中发送命令确认，不等待发送完成，命令流已断开或缓冲已满时丢弃
+    fn send_ack(&self, command_type: CommandType, success: bool) {
+        let Some(tx) = &self.command_tx else {
+            return;
+        };
+        let mut request = self.create_command_request();
+        request.ack = Some(CommandAck {
+            server_id: self.server_id,
+            command_type: command_type.into(),
+            success,
+        });
+        if let Err(e) = tx.try_send(request) {
+            tracing::debug!("发送命令确认失败: {}", e);
+        }
+    }
+
+    /// 调整状态上报间隔，限制在安全范围内，并保证 CPU 采样间隔不小于 sysinfo 的最小刷新间隔
+    fn set_report_interval(&mut self, secs: u64) {
+        let min = MIN_REMOTE_INTERVAL.max(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL * self.cpu_samples);
+        let interval = Duration::from_secs(secs).min(MAX_REMOTE_INTERVAL).max(min);
+        if interval != Duration::from_secs(secs) {
+            tracing::warn!("上报间隔 {}s 超出允许范围", secs);
+        }
+        tracing::info!(
+            "状态上报间隔已从 {}ms 调整为 {}ms",
+            self.report_interval.as_millis(),
+            interval.as_millis()
+        );
+        self.report_interval = interval;
+    }
+
     /// 开始定期上报状态
-    async fn start_reporting_state(&mut self) -> anyhow::Result<()> {
-        let mut interval = tokio::time::interval(Duration::from_secs(1));
-        
-        while self.report_state {
+    /// `rate` 模式保持固定上报节奏，`delay` 模式在每次上报完成后等待完整间隔，避免慢网络下上报堆积
+    /// 每轮上报前处理期间收到的命令，如调整上报间隔或停止上报
+    async fn start_reporting_state(
+        &mut self,
+        commands: &mut Streaming<common::panda_monitor::Command>,
+    ) -> anyhow::Result<()> {
+        let mut interval = tokio::time::interval(self.report_interval);
+
+        loop {
             let start = tokio::time::Instant::now();
-            if let Err(e) = self.report_server_state().await {
-                eprintln!("状态上报失败: {}", e);
+            let report_interval = self.report_interval;
+            // 上报期间命令流结束时结束本次会话，由外层重新建立命令流
+            loop {
+                match commands.next().now_or_never() {
+                    Some(Some(result)) => self.parse_command(result).await?,
+                    Some(None) => return Err(anyhow::anyhow!("命令流已关闭")),
+                    None => break,
+                }
             }
-           
-            // 计算剩余时间
-            let elapsed = start.elapsed();
-            if elapsed < Duration::from_secs(1) {
-                interval.tick().await;
-            } else {
-                // 如果超时，立即开始下一轮
-                interval.reset();
+            if !self.report_state {
+                break;
+            }
+            if self.report_interval != report_interval {
+                interval = time::interval_at(start + self.report_interval, self.report_interval);
             }
+            if let Err(e) = self.report_server_state(false).await {
+                tracing::error!("状态上报失败: {}", e);
+            }
+            self.latency_stats.print_if_due(self.server_id);
+            self.report_host_if_due().await;
+
+            let sample_from = match self.interval_mode {
+                IntervalMode::Rate => start,
+                IntervalMode::Delay => tokio::time::Instant::now(),
+            };
+            self.sample_cpu_between_reports(sample_from).await;
+
+            match self.interval_mode {
+                IntervalMode::Rate => {
+                    // 计算剩余时间
+                    let elapsed = start.elapsed();
+                    if elapsed < self.report_interval {
+                        interval.tick().await;
+                    } else {
+                        // 如果超时，立即开始下一轮
+                        interval.reset();
+                    }
+                }
+                IntervalMode::Delay => time::sleep_until(sample_from + self.report_interval).await,
+            }
+        }
+        if let Err(e) = self.close_state_stream().await {
+            tracing::error!("关闭状态上报流失败: {}", e);
         }
         Ok(())
     }
 
+    /// 在两次上报之间均匀采样 CPU 使用率，采样次数为 1 时不额外采样
+    async fn sample_cpu_between_reports(&mut self, from: tokio::time::Instant) {
+        for i in 1..self.cpu_samples {
+            time::sleep_until(from + self.report_interval * i / self.cpu_samples).await;
+            self.system_info.sample_cpu();
+        }
+    }
+
     /// 上报服务器状态
-    async fn report_server_state(&mut self) -> anyhow::Result<()> {
+    /// `force` 为 true 时忽略自适应上报过滤，一定发送
+    async fn report_server_state(&mut self, force: bool) -> anyhow::Result<()> {
         // 上报前检查连接状态
         // if let Err(e) = self.check_connection().await {
         //     eprintln!("连接检查失败: {}", e);
@@ -186,24 +487,26 @@ impl ServerMonitorAgent {
 
         let mut attempts = 0;
 
-        while attempts < RETRY_ATTEMPTS {
-            match self.try_report_state().await {
+        while attempts < self.retry_attempts {
+            match self.try_report_state(force).await {
                 Ok(_) => return Ok(()),
                 Err(e) => {
                     attempts += 1;
-                    if attempts == RETRY_ATTEMPTS {
+                    self.failover_if_needed().await;
+                    if attempts == self.retry_attempts {
                         return Err(anyhow::anyhow!(
                             "状态上报失败，已重试 {} 次: {}",
-                            RETRY_ATTEMPTS,
+                            self.retry_attempts,
                             e
-                        )
-                        .into());
+                        ));
                     }
-                    println!(
+                    tracing::warn!(
                         "状态上报失败，正在重试 ({}/{}): {}",
-                        attempts, RETRY_ATTEMPTS, e
+                        attempts,
+                        self.retry_attempts,
+                        e
                     );
-                    time::sleep(Duration::from_secs(RETRY_DELAY_SECS)).await;
+                    time::sleep(self.backoff.delay(attempts)).await;
                 }
             }
         }
@@ -212,63 +515,282 @@ impl ServerMonitorAgent {
     }
 
     /// 尝试上报单次状态
-    async fn try_report_state(&mut self) -> anyhow::Result<()> {
+    /// 定期上报写入持续的上报流，`force` 为 true 时单独发起一次请求并等待服务端确认
+    async fn try_report_state(&mut self, force: bool) -> anyhow::Result<()> {
         self.refresh_system_components();
+        if self.boot_time_changed() {
+            tracing::warn!("检测到主机开机时间变化，主机已重启，重新上报主机信息");
+            self.rebooted = true;
+            if let Err(e) = self.report_server_host().await {
+                tracing::error!("主机信息上报失败: {}", e);
+            }
+        }
 
-        let (tx, rx) = mpsc::channel(128);
         let request = self.create_state_request().await;
+        let state = request.state.clone().unwrap_or_default();
+
+        // 状态没有明显变化时跳过本次上报，重启标记需要尽快送达
+        if !force && !self.rebooted && !self.report_filter.should_send(&state) {
+            return Ok(());
+        }
 
-        tx.send(request)
-            .await
-            .map_err(|e| anyhow::anyhow!("发送状态请求失败: {}", e))?;
         let start = tokio::time::Instant::now();
-        let _ = self.client.report_server_state(ReceiverStream::new(rx)).await;
-        println!("rpc client 上报耗时: {:?}", start.elapsed());
-        // let _response = time::timeout(
-        //     Duration::from_secs(GRPC_TIMEOUT_SECS),
-        //     self.client.report_server_state(ReceiverStream::new(rx)),
-        // )
-        // .await;
-
-        // if !response.get_ref().success {
-        //     return Err(anyhow::anyhow!("服务器返回状态上报失败"));
-        // }
+        if force {
+            self.send_state_once(request).await?;
+        } else {
+            self.send_state_to_stream(request).await?;
+        }
+        let elapsed = start.elapsed();
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+        if self.report_timing {
+            tracing::info!(server_id = self.server_id, elapsed_ms, "状态上报完成");
+        } else {
+            tracing::debug!(server_id = self.server_id, elapsed_ms, "状态上报完成");
+        }
+        self.latency_stats.record(elapsed);
+        self.report_filter.record_sent(state);
+        self.rebooted = false;
 
         Ok(())
     }
 
-    /// 创建命令请求
-    fn create_command_request(&mut self) -> CommandRequest {
-        CommandRequest {
-            agent_info: Some(AgentInfo {
-                agent_version: VERSION.to_string(),
-                server_id: self.server_id,
-            }),
+    /// 单独发起一次状态上报请求
+    async fn send_state_once(&mut self, request: StateRequest) -> anyhow::Result<()> {
+        let request = self.timed_request(tokio_stream::iter([request]));
+        let response = match self.client.report_server_state(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.record_failure(&e);
+                return Err(anyhow::anyhow!(
+                    "状态上报请求失败 ({:?}): {}",
+                    e.code(),
+                    e.message()
+                ));
+            }
+        };
+        self.consecutive_failures = 0;
+        self.check_response("状态上报", response.get_ref())
+    }
+
+    /// 把状态写入上报流，流不存在时先建立
+    /// 服务端拒绝或连接断开时流会提前结束，此时返回结束原因，下次上报重新建立流
+    async fn send_state_to_stream(&mut self, request: StateRequest) -> anyhow::Result<()> {
+        if self
+            .state_stream
+            .as_ref()
+            .is_some_and(|stream| stream.response.is_finished())
+        {
+            self.close_state_stream().await?;
+        }
+
+        let tx = self
+            .state_stream
+            .get_or_insert_with(|| open_state_stream(self.client.clone(), self.state_buffer))
+            .tx
+            .clone();
+        if tx.send(request).await.is_err() {
+            self.close_state_stream().await?;
+            return Err(anyhow::anyhow!("状态上报流已关闭"));
+        }
+        Ok(())
+    }
+
+    /// 结束状态上报流并检查服务端的响应
+    /// 写入上报流的状态在入队时就记为已发送，流异常结束时这些状态可能没有送达，
+    /// 此时清空过滤器的记录，避免下一次状态因与未送达的状态相近而被跳过
+    async fn close_state_stream(&mut self) -> anyhow::Result<()> {
+        let result = self.finish_state_stream().await;
+        if result.is_err() {
+            self.report_filter.reset();
+        }
+        result
+    }
+
+    /// 关闭上报流的发送端，等待服务端返回响应
+    async fn finish_state_stream(&mut self) -> anyhow::Result<()> {
+        let Some(StateStream { tx, mut response }) = self.state_stream.take() else {
+            return Ok(());
+        };
+        // 关闭发送端后服务端处理完剩余状态并返回响应
+        drop(tx);
+        let result = match time::timeout(self.grpc_timeout, &mut response).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => return Err(anyhow::anyhow!("状态上报任务异常退出: {}", e)),
+            Err(_) => {
+                response.abort();
+                return Err(anyhow::anyhow!("等待状态上报流结束超时"));
+            }
+        };
+        match result {
+            Ok(response) => {
+                self.consecutive_failures = 0;
+                self.check_response("状态上报", response.get_ref())
+            }
+            Err(e) => {
+                self.record_failure(&e);
+                Err(anyhow::anyhow!(
+                    "状态上报请求失败 ({:?}): {}",
+                    e.code(),
+                    e.message()
+                ))
+            }
         }
     }
 
+    /// 检查主机开机时间是否与上一次状态上报时不同
+    /// 开机时间由当前时间和运行时长推算，时钟调整会带来少量偏差，不超过容差时忽略
+    fn boot_time_changed(&mut self) -> bool {
+        let boot_time = sysinfo::System::boot_time();
+        self.last_boot_time
+            .replace(boot_time)
+            .is_some_and(|last| last.abs_diff(boot_time) > BOOT_TIME_TOLERANCE_SECS)
+    }
+
+    /// 上报主机信息
+    async fn report_server_host(&mut self) -> anyhow::Result<()> {
+        self.refresh_system_components();
+        let request = self.timed_request(tokio_stream::iter([self.create_host_request().await]));
+        let response = self.client.report_server_host(request).await.map_err(|e| {
+            anyhow::anyhow!("主机信息上报请求失败 ({:?}): {}", e.code(), e.message())
+        })?;
+        self.check_response("主机信息上报", response.get_ref())
+    }
+
+    /// 距离下一次定期上报主机信息的时间，不再需要上报时返回 None
+    fn next_host_report_in(&self) -> Option<Duration> {
+        match self.last_host_report {
+            None => Some(Duration::ZERO),
+            Some(_) if self.host_report_interval.is_zero() => None,
+            Some(last) => Some(self.host_report_interval.saturating_sub(last.elapsed())),
+        }
+    }
+
+    /// 到达上报间隔时上报主机信息
+    /// 失败时不立即重试，等待下一个间隔，避免服务器不可用时反复采集和请求
+    async fn report_host_if_due(&mut self) {
+        if self.next_host_report_in() != Some(Duration::ZERO) {
+            return;
+        }
+        if let Err(e) = self.report_server_host().await {
+            tracing::error!("主机信息上报失败: {}", e);
+        }
+        self.last_host_report = Some(time::Instant::now());
+    }
+
+    /// 上报 IP 地址
+    async fn update_ip(&mut self) -> anyhow::Result<()> {
+        let request = self.timed_request(self.create_update_ip_request().await);
+        let response =
+            self.client.update_ip(request).await.map_err(|e| {
+                anyhow::anyhow!("IP 上报请求失败 ({:?}): {}", e.code(), e.message())
+            })?;
+        self.check_response("IP 上报", response.get_ref())
+    }
+
+    /// 检查服务端响应，失败时返回服务端给出的原因
+    fn check_response(&mut self, action: &str, response: &ServerResponse) -> anyhow::Result<()> {
+        self.check_supported_version(&response.min_supported_version);
+        if !response.success {
+            let reason = if response.message.is_empty() {
+                "未说明原因"
+            } else {
+                response.message.as_str()
+            };
+            return Err(anyhow::anyhow!("服务器拒绝{}: {}", action, reason));
+        }
+        Ok(())
+    }
+
+    /// 记录一次请求失败，只统计无法连接或超时的错误，服务端拒绝不计入
+    fn record_failure(&mut self, status: &Status) {
+        if matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded) {
+            self.consecutive_failures += 1;
+        }
+    }
+
+    /// 当前服务器连续无法连接的次数达到阈值时，按顺序切换到下一个可连接的地址
+    /// 所有地址都无法连接时保持当前地址，等待下一轮失败后再次尝试
+    async fn failover_if_needed(&mut self) {
+        if self.endpoints.len() < 2 || self.consecutive_failures < self.failover_after {
+            return;
+        }
+        self.consecutive_failures = 0;
+
+        let failed_uri = self.endpoints[self.current_endpoint].to_string();
+        let start = (self.current_endpoint + 1) % self.endpoints.len();
+        match connect_any(&self.endpoints, start).await {
+            Ok((index, channel)) => {
+                tracing::warn!(
+                    "服务器 {} 无法连接，已切换到 {}",
+                    failed_uri,
+                    self.endpoints[index]
+                );
+                self.current_endpoint = index;
+                self.client = PandaMonitorClient::new(channel);
+                // 旧服务器上的上报流不再可用
+                if let Some(stream) = self.state_stream.take() {
+                    stream.response.abort();
+                }
+            }
+            Err(e) => tracing::warn!("服务器 {} 无法连接，切换地址失败: {}", failed_uri, e),
+        }
+    }
+
+    /// 检查探针版本是否低于服务端支持的最低版本
+    /// 只提示升级，不影响上报，且只提示一次
+    fn check_supported_version(&mut self, min_supported_version: &str) {
+        if self.version_warned {
+            return;
+        }
+        // 旧版本服务端不返回该字段，解析失败时跳过检查
+        let (Ok(current), Ok(min_supported)) = (
+            VERSION.parse::<Version>(),
+            min_supported_version.parse::<Version>(),
+        ) else {
+            return;
+        };
+        if current < min_supported {
+            self.version_warned = true;
+            tracing::warn!(
+                "探针版本 {} 低于服务端支持的最低版本 {}，请尽快升级探针",
+                current,
+                min_supported
+            );
+        }
+    }
+
+    /// 创建命令请求
+    fn create_command_request(&self) -> CommandRequest {
+        command_request(self.server_id, &self.tenant_id)
+    }
+
+    /// 请求中携带的探针信息
+    fn agent_info(&self) -> AgentInfo {
+        agent_info(self.server_id, &self.tenant_id)
+    }
+
     /// 创建状态请求
     async fn create_state_request(&self) -> StateRequest {
+        let mut state = self.get_server_state();
+        state.rebooted_since_last_report = self.rebooted;
+        (state.custom_metrics, state.checks) = tokio::join!(
+            collect_custom_metrics(&self.collector_scripts),
+            run_checks(&self.checks, self.check_timeout)
+        );
         StateRequest {
-            agent_info: Some(AgentInfo {
-                agent_version: VERSION.to_string(),
-                server_id: self.server_id,
-            }),
-            state: Some(self.get_server_state()),
+            agent_info: Some(self.agent_info()),
+            state: Some(state),
             upload_time: self.get_upload_time(),
         }
     }
 
     /// 创建更新IP请求
     async fn create_update_ip_request(&self) -> UpdateIpRequest {
-        let geo_ip = fetch_geo_ip().await;
+        let geo_ip = self.system_info.get_ip().await;
         UpdateIpRequest {
             ipv4: geo_ip.ipv4,
             ipv6: geo_ip.ipv6,
-            agent_info: Some(AgentInfo {
-                agent_version: VERSION.to_string(),
-                server_id: self.server_id,
-            }),
+            agent_info: Some(self.agent_info()),
             upload_time: self.get_upload_time(),
         }
     }
@@ -277,10 +799,7 @@ impl ServerMonitorAgent {
     async fn create_host_request(&self) -> HostRequest {
         HostRequest {
             host: Some(self.get_server_host().await),
-            agent_info: Some(AgentInfo {
-                agent_version: VERSION.to_string(),
-                server_id: self.server_id,
-            }),
+            agent_info: Some(self.agent_info()),
             upload_time: self.get_upload_time(),
         }
     }
@@ -300,6 +819,13 @@ impl ServerMonitorAgent {
         self.system_info.get_system_state()
     }
 
+    /// 构建带超时时间的请求
+    fn timed_request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.set_timeout(self.grpc_timeout);
+        request
+    }
+
     /// 获取当前时间戳（秒）
     fn get_upload_time(&self) -> u64 {
         SystemTime::now()
@@ -311,12 +837,363 @@ impl ServerMonitorAgent {
     pub async fn shutdown(&mut self) -> anyhow::Result<()> {
         if self.report_state {
             self.report_state = false;
-            println!("正在停止状态上报...");
+            tracing::info!("正在停止状态上报...");
+            if let Err(e) = self.close_state_stream().await {
+                tracing::error!("关闭状态上报流失败: {}", e);
+            }
 
             // 发送最后一次状态报告
-            self.report_server_state().await?;
+            self.report_server_state(true).await?;
         }
-        println!("探针已关闭");
+        tracing::info!("探针已关闭");
         Ok(())
     }
 }
+
+/// 服务器连接端点
+/// `unix://` 地址通过 Unix 域套接字连接，其余地址通过 TCP 连接
+#[derive(Debug, Clone)]
+pub struct ServerEndpoint {
+    endpoint: Endpoint,
+    /// Unix 域套接字路径，TCP 地址为 None
+    unix_path: Option<PathBuf>,
+}
+
+impl ServerEndpoint {
+    /// 建立连接
+    pub async fn connect(&self) -> Result<Channel, tonic::transport::Error> {
+        match &self.unix_path {
+            #[cfg(unix)]
+            Some(path) => {
+                let path = path.clone();
+                // Unix 域套接字不使用端点中的地址，每次重连时重新连接套接字文件
+                let connector = tower::service_fn(move |_: tonic::transport::Uri| {
+                    let path = path.clone();
+                    async move {
+                        let stream = tokio::net::UnixStream::connect(path).await?;
+                        Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+                    }
+                });
+                self.endpoint.connect_with_connector(connector).await
+            }
+            _ => self.endpoint.connect().await,
+        }
+    }
+
+    /// TCP 地址，Unix 域套接字地址时为 None
+    pub fn tcp_uri(&self) -> Option<&tonic::transport::Uri> {
+        match self.unix_path {
+            Some(_) => None,
+            None => Some(self.endpoint.uri()),
+        }
+    }
+
+    /// Unix 域套接字路径，TCP 地址时为 None
+    pub fn unix_path(&self) -> Option<&PathBuf> {
+        self.unix_path.as_ref()
+    }
+}
+
+impl fmt::Display for ServerEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.unix_path {
+            Some(path) => write!(f, "unix://{}", path.display()),
+            None => write!(f, "{}", self.endpoint.uri()),
+        }
+    }
+}
+
+/// 根据命令行参数构建所有服务器的连接端点，顺序与 `--url` 一致
+pub fn build_endpoints(command: &Command) -> anyhow::Result<Vec<ServerEndpoint>> {
+    let tls_config = load_tls_config(command)?;
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "grpc"
+    };
+    let grpc_timeout = Duration::from_secs(command.grpc_timeout);
+    // `--uds` 优先于 `--url`
+    let urls = match &command.uds {
+        Some(path) => vec![format!("{}{}", UNIX_SCHEME, path.trim())],
+        None => command.url.clone(),
+    };
+
+    urls.iter()
+        .map(|url| {
+            let url = url.trim();
+            if let Some(path) = url.strip_prefix(UNIX_SCHEME) {
+                if cfg!(not(unix)) {
+                    return Err(anyhow::anyhow!("当前平台不支持 Unix 域套接字: {}", url));
+                }
+                if path.is_empty() {
+                    return Err(anyhow::anyhow!("Unix 域套接字路径不能为空: {}", url));
+                }
+                // 端点地址只用于请求头，实际连接由 `ServerEndpoint::connect` 处理
+                let endpoint = Endpoint::from_static(UNIX_ENDPOINT_PLACEHOLDER)
+                    .connect_timeout(grpc_timeout)
+                    .concurrency_limit(command.concurrency_limit);
+                return Ok(ServerEndpoint {
+                    endpoint,
+                    unix_path: Some(PathBuf::from(path)),
+                });
+            }
+            let url = build_server_url(scheme, url, command.port)?;
+            // 请求超时由各请求单独设置，持续的状态上报流不受超时限制
+            let mut endpoint = Channel::from_shared(url)?
+                .connect_timeout(grpc_timeout)
+                .concurrency_limit(command.concurrency_limit);
+            if let Some(tls_config) = &tls_config {
+                endpoint = endpoint.tls_config(tls_config.clone())?;
+            }
+            Ok(ServerEndpoint {
+                endpoint,
+                unix_path: None,
+            })
+        })
+        .collect()
+}
+
+/// 从下标 `start` 开始按顺序尝试连接，返回第一个连接成功的地址下标和通道
+/// 全部失败时返回最后一个错误
+async fn connect_any(
+    endpoints: &[ServerEndpoint],
+    start: usize,
+) -> anyhow::Result<(usize, Channel)> {
+    let mut last_error = anyhow::anyhow!("没有可用的服务器地址");
+    for offset in 0..endpoints.len() {
+        let index = (start + offset) % endpoints.len();
+        match endpoints[index].connect().await {
+            Ok(channel) => return Ok((index, channel)),
+            Err(e) => {
+                if endpoints.len() > 1 {
+                    tracing::warn!("连接 {} 失败: {}", endpoints[index], e);
+                }
+                last_error = anyhow::anyhow!("{}: {}", endpoints[index], e);
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// 在后台发起状态上报请求，返回写入状态的发送端
+fn open_state_stream(mut client: PandaMonitorClient<Channel>, buffer: usize) -> StateStream {
+    let (tx, rx) = mpsc::channel(buffer);
+    let response =
+        tokio::spawn(async move { client.report_server_state(ReceiverStream::new(rx)).await });
+    StateStream { tx, response }
+}
+
+/// 创建命令请求
+pub fn command_request(server_id: u64, tenant_id: &str) -> CommandRequest {
+    CommandRequest {
+        agent_info: Some(agent_info(server_id, tenant_id)),
+        ack: None,
+    }
+}
+
+/// 创建请求中携带的探针信息
+fn agent_info(server_id: u64, tenant_id: &str) -> AgentInfo {
+    AgentInfo {
+        agent_version: VERSION.to_string(),
+        server_id,
+        tenant_id: tenant_id.to_string(),
+    }
+}
+
+/// 加载连接服务器的 TLS 配置，未指定 CA 证书时返回 None，使用明文传输
+fn load_tls_config(command: &Command) -> anyhow::Result<Option<ClientTlsConfig>> {
+    let Some(ca_path) = &command.ca_cert else {
+        return Ok(None);
+    };
+    let ca = std::fs::read(ca_path)
+        .map_err(|e| anyhow::anyhow!("读取 CA 证书 {} 失败: {}", ca_path, e))?;
+    let mut tls_config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca));
+
+    // 配置客户端证书，用于服务端认证探针
+    if let (Some(cert_path), Some(key_path)) = (&command.client_cert, &command.client_key) {
+        let cert = std::fs::read(cert_path)
+            .map_err(|e| anyhow::anyhow!("读取客户端证书 {} 失败: {}", cert_path, e))?;
+        let key = std::fs::read(key_path)
+            .map_err(|e| anyhow::anyhow!("读取客户端私钥 {} 失败: {}", key_path, e))?;
+        tls_config = tls_config.identity(Identity::from_pem(cert, key));
+    }
+    Ok(Some(tls_config))
+}
+
+/// 构建服务器地址
+/// `url` 已包含协议时直接使用；IPv6 地址会加上方括号，如 `grpc://[::1]:50051`
+fn build_server_url(scheme: &str, url: &str, port: Option<u16>) -> anyhow::Result<String> {
+    if url.contains("://") {
+        return Ok(url.to_string());
+    }
+    let port = port.ok_or_else(|| anyhow::anyhow!("地址 {} 缺少端口号", url))?;
+    if url.parse::<Ipv6Addr>().is_ok() {
+        return Ok(format!("{}://[{}]:{}", scheme, url, port));
+    }
+    Ok(format!("{}://{}:{}", scheme, url, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use tokio::net::TcpListener;
+
+    /// 启动一个不提供任何服务的本地 gRPC 服务器，只用于建立连接，返回监听端口
+    async fn spawn_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        serve(listener);
+        port
+    }
+
+    /// 在已绑定的端口上提供 gRPC 服务
+    fn serve(listener: TcpListener) {
+        let incoming = futures::stream::unfold(listener, |listener| async move {
+            Some((listener.accept().await.map(|(stream, _)| stream), listener))
+        });
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_routes(tonic::service::Routes::default())
+                .serve_with_incoming(incoming),
+        );
+    }
+
+    /// 获取一个没有程序监听的本地端口
+    async fn closed_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    /// 第一个地址始终无法连接，第二个地址可以连接
+    fn command(closed: u16, live: u16, failover_after: u32) -> Command {
+        Command::parse_from([
+            "agent",
+            "-a",
+            "1",
+            "--ip-mode",
+            "local",
+            "--failover-after",
+            &failover_after.to_string(),
+            "-u",
+            &format!("grpc://127.0.0.1:{},grpc://127.0.0.1:{}", closed, live),
+        ])
+    }
+
+    /// 只有一个地址、不等待直接重试的配置
+    fn single_endpoint_command(port: u16) -> Command {
+        Command::parse_from([
+            "agent",
+            "-a",
+            "1",
+            "--ip-mode",
+            "local",
+            "--retry-attempts",
+            "1",
+            "--retry-delay",
+            "0",
+            "--retry-delay-max",
+            "0",
+            "-u",
+            &format!("grpc://127.0.0.1:{}", port),
+        ])
+    }
+
+    #[test]
+    fn server_url_formats() {
+        let cases = [
+            ("grpc", "1.2.3.4", Some(50051), "grpc://1.2.3.4:50051"),
+            ("grpc", "::1", Some(50051), "grpc://[::1]:50051"),
+            (
+                "https",
+                "2001:db8::1",
+                Some(443),
+                "https://[2001:db8::1]:443",
+            ),
+            (
+                "grpc",
+                "monitor.example.com",
+                Some(50051),
+                "grpc://monitor.example.com:50051",
+            ),
+            (
+                "grpc",
+                "https://example.com:8443",
+                Some(50051),
+                "https://example.com:8443",
+            ),
+            ("https", "grpc://[::1]:50051", None, "grpc://[::1]:50051"),
+        ];
+        for (scheme, url, port, expected) in cases {
+            assert_eq!(
+                build_server_url(scheme, url, port).unwrap(),
+                expected,
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn server_url_requires_port_without_scheme() {
+        assert!(build_server_url("grpc", "1.2.3.4", None).is_err());
+        assert!(build_server_url("grpc", "::1", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn connect_any_skips_unreachable_endpoint() {
+        let command = command(closed_port().await, spawn_server().await, 1);
+        let endpoints = build_endpoints(&command).unwrap();
+
+        let (index, _) = connect_any(&endpoints, 0).await.unwrap();
+        assert_eq!(index, 1);
+    }
+
+    #[tokio::test]
+    async fn failover_after_consecutive_failures() {
+        let command = command(closed_port().await, spawn_server().await, 3);
+        let mut agent = ServerMonitorAgent::new(command).await.unwrap();
+        assert_eq!(agent.current_endpoint, 1);
+
+        // 模拟探针原本连接第一个地址，随后该服务器下线
+        agent.current_endpoint = 0;
+        for _ in 0..2 {
+            agent.record_failure(&Status::unavailable("connection refused"));
+            agent.failover_if_needed().await;
+            assert_eq!(agent.current_endpoint, 0);
+        }
+        // 服务端拒绝不计入连续失败次数
+        agent.record_failure(&Status::permission_denied("rejected"));
+        agent.failover_if_needed().await;
+        assert_eq!(agent.current_endpoint, 0);
+
+        agent.record_failure(&Status::unavailable("connection refused"));
+        agent.failover_if_needed().await;
+        assert_eq!(agent.current_endpoint, 1);
+        assert_eq!(agent.consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn persistent_agent_waits_for_server() {
+        let port = closed_port().await;
+        // 单次上报使用的实例在重试次数用完后返回错误
+        assert!(ServerMonitorAgent::new(single_endpoint_command(port))
+            .await
+            .is_err());
+
+        // 持续运行的实例一直重试，直到服务端启动
+        let agent = tokio::spawn(ServerMonitorAgent::new_persistent(single_endpoint_command(
+            port,
+        )));
+        time::sleep(Duration::from_millis(300)).await;
+        assert!(!agent.is_finished());
+
+        serve(TcpListener::bind(("127.0.0.1", port)).await.unwrap());
+        let agent = time::timeout(Duration::from_secs(5), agent)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(agent.current_endpoint, 0);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sysinfo::Networks;
+
+// 常量定义
+const SAVE_INTERVAL: Duration = Duration::from_secs(60); // 状态文件的最短写入间隔
+const U32_COUNTER_RANGE: u64 = 1 << 32; // 32 位网卡计数器的取值范围
+const WRAP_WINDOW: u64 = 1 << 30; // 回绕前后计数值距边界在此范围内时视为 32 位计数器回绕，否则视为重置
+
+/// 持久化的网络流量状态
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TransferState {
+    /// 累计接收字节数
+    total_in: u64,
+    /// 累计发送字节数
+    total_out: u64,
+    /// 各网卡上一次采样的原始计数值 (接收, 发送)
+    interfaces: HashMap<String, (u64, u64)>,
+}
+
+/// 网络总流量跟踪器
+/// 按网卡记录原始计数值，把每次采样的增量累加到总流量上，并定期写入状态文件，
+/// 探针重启或主机重启导致计数器归零时总流量保持单调递增
+#[derive(Debug)]
+pub struct TransferTracker {
+    path: PathBuf,
+    state: TransferState,
+    /// 上一次写入状态文件的时间，None 表示尚未写入
+    last_saved: Option<Instant>,
+}
+
+impl TransferTracker {
+    /// 从状态文件加载，文件不存在时从零开始累计
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let state = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("解析状态文件 {} 失败: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => TransferState::default(),
+            Err(e) => return Err(anyhow::anyhow!("读取状态文件 {} 失败: {}", path, e)),
+        };
+        Ok(Self {
+            path: PathBuf::from(path),
+            state,
+            last_saved: None,
+        })
+    }
+
+    /// 根据最新的网卡计数更新总流量，到达写入间隔时保存状态文件
+    pub fn update(&mut self, networks: &Networks) {
+        let mut interfaces = HashMap::with_capacity(networks.list().len());
+        for (name, data) in networks.list() {
+            let current = (data.total_received(), data.total_transmitted());
+            // 首次出现的网卡按计数器从零开始计算
+            let last = self.state.interfaces.get(name).copied().unwrap_or_default();
+            self.state.total_in = self
+                .state
+                .total_in
+                .saturating_add(counter_delta(last.0, current.0));
+            self.state.total_out = self
+                .state
+                .total_out
+                .saturating_add(counter_delta(last.1, current.1));
+            interfaces.insert(name.clone(), current);
+        }
+        // 只保留当前存在的网卡，避免容器虚拟网卡不断累积
+        self.state.interfaces = interfaces;
+
+        if self
+            .last_saved
+            .is_none_or(|last| last.elapsed() >= SAVE_INTERVAL)
+        {
+            if let Err(e) = self.save() {
+                tracing::error!("{}", e);
+            }
+            self.last_saved = Some(Instant::now());
+        }
+    }
+
+    /// 累计接收和发送字节数
+    pub fn totals(&self) -> (u64, u64) {
+        (self.state.total_in, self.state.total_out)
+    }
+
+    /// 先写入临时文件再重命名，避免写入中断导致状态文件损坏
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow::anyhow!("创建目录 {} 失败: {}", parent.display(), e))?;
+        }
+        let content = serde_json::to_string(&self.state)?;
+        let tmp_path = tmp_path(&self.path);
+        std::fs::write(&tmp_path, content)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.path))
+            .map_err(|e| anyhow::anyhow!("写入状态文件 {} 失败: {}", self.path.display(), e))
+    }
+}
+
+/// 计算两次采样之间的计数增量
+/// 计数值变小时，上一次接近 32 位上限且当前值很小视为计数器回绕，否则视为计数器重置（如主机重启）
+fn counter_delta(last: u64, current: u64) -> u64 {
+    if current >= last {
+        return current - last;
+    }
+    let wrapped = last < U32_COUNTER_RANGE
+        && U32_COUNTER_RANGE - last <= WRAP_WINDOW
+        && current <= WRAP_WINDOW;
+    if wrapped {
+        U32_COUNTER_RANGE - last + current
+    } else {
+        current
+    }
+}
+
+/// 状态文件对应的临时文件路径
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
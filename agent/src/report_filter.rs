@@ -0,0 +1,223 @@
+use std::time::{Duration, Instant};
+
+use common::panda_monitor::State;
+
+use crate::command::Command;
+
+// 常量定义
+const BYTES_PER_MB: f64 = 1024.0 * 1024.0; // 1 MB 的字节数
+
+/// 状态上报过滤器
+/// 与上一次发送的状态相比，所有指标的变化都在阈值内时跳过本次上报，
+/// 但距上一次发送超过 `max_report_gap` 时仍会发送一次作为保活。
+#[derive(Debug)]
+pub struct ReportFilter {
+    /// CPU 使用率阈值（百分比）
+    cpu_epsilon: f64,
+    /// 内存、交换空间和磁盘用量阈值（字节）
+    mem_epsilon: u64,
+    /// 网络、磁盘 IO 和交换空间换入换出速度阈值（字节/秒）
+    speed_epsilon: u64,
+    /// 系统负载阈值，同时用于每核负载
+    load_epsilon: f64,
+    /// 打开文件描述符数和 TCP 连接数阈值
+    count_epsilon: u64,
+    /// 两次上报的最大间隔，为 0 时不跳过任何上报
+    max_report_gap: Duration,
+    /// 上一次发送的状态及发送时间
+    last_sent: Option<(State, Instant)>,
+}
+
+impl ReportFilter {
+    pub fn new(command: &Command) -> Self {
+        Self {
+            cpu_epsilon: command.cpu_epsilon,
+            mem_epsilon: (command.mem_epsilon * BYTES_PER_MB) as u64,
+            speed_epsilon: command.speed_epsilon,
+            load_epsilon: command.load_epsilon,
+            count_epsilon: command.count_epsilon,
+            max_report_gap: Duration::from_secs(command.max_report_gap),
+            last_sent: None,
+        }
+    }
+
+    /// 判断是否需要发送该状态
+    pub fn should_send(&self, state: &State) -> bool {
+        if self.max_report_gap.is_zero() {
+            return true;
+        }
+        let Some((last, sent_at)) = &self.last_sent else {
+            return true;
+        };
+        sent_at.elapsed() >= self.max_report_gap || self.changed(last, state)
+    }
+
+    /// 记录已成功发送的状态
+    pub fn record_sent(&mut self, state: State) {
+        self.last_sent = Some((state, Instant::now()));
+    }
+
+    /// 清空已发送的记录，下一次状态不再被过滤
+    pub fn reset(&mut self) {
+        self.last_sent = None;
+    }
+
+    /// 判断状态是否有超出阈值的变化
+    /// 比较除累计流量外的所有指标；累计流量随网速单调增长，由网速的变化体现
+    fn changed(&self, last: &State, state: &State) -> bool {
+        let exceeds = |a: u64, b: u64, epsilon: u64| a.abs_diff(b) > epsilon;
+
+        (last.cpu_usage - state.cpu_usage).abs() > self.cpu_epsilon
+            || exceeds(last.mem_used, state.mem_used, self.mem_epsilon)
+            || exceeds(last.mem_available, state.mem_available, self.mem_epsilon)
+            || exceeds(last.mem_used_real, state.mem_used_real, self.mem_epsilon)
+            || exceeds(last.swap_used, state.swap_used, self.mem_epsilon)
+            || exceeds(last.disk_used, state.disk_used, self.mem_epsilon)
+            || exceeds(last.net_in_speed, state.net_in_speed, self.speed_epsilon)
+            || exceeds(last.net_out_speed, state.net_out_speed, self.speed_epsilon)
+            || exceeds(
+                last.disk_read_speed,
+                state.disk_read_speed,
+                self.speed_epsilon,
+            )
+            || exceeds(
+                last.disk_write_speed,
+                state.disk_write_speed,
+                self.speed_epsilon,
+            )
+            || exceeds(last.swap_in_rate, state.swap_in_rate, self.speed_epsilon)
+            || exceeds(last.swap_out_rate, state.swap_out_rate, self.speed_epsilon)
+            || (last.load1 - state.load1).abs() > self.load_epsilon
+            || (last.load5 - state.load5).abs() > self.load_epsilon
+            || (last.load15 - state.load15).abs() > self.load_epsilon
+            || (last.load1_per_core - state.load1_per_core).abs() > self.load_epsilon
+            || (last.load5_per_core - state.load5_per_core).abs() > self.load_epsilon
+            || (last.load15_per_core - state.load15_per_core).abs() > self.load_epsilon
+            || exceeds(last.open_fds, state.open_fds, self.count_epsilon)
+            || exceeds(
+                last.tcp_connections,
+                state.tcp_connections,
+                self.count_epsilon,
+            )
+            || exceeds(
+                last.tcp_established,
+                state.tcp_established,
+                self.count_epsilon,
+            )
+            || exceeds(last.tcp_listen, state.tcp_listen, self.count_epsilon)
+            || last.custom_metrics != state.custom_metrics
+            || checks_changed(last, state)
+            || self.gpus_changed(last, state)
+            || self.watched_processes_changed(last, state)
+    }
+
+    /// 判断 GPU 使用率和显存是否有超出阈值的变化，使用率沿用 CPU 阈值，显存沿用内存阈值
+    fn gpus_changed(&self, last: &State, state: &State) -> bool {
+        last.gpus.len() != state.gpus.len()
+            || last.gpus.iter().zip(&state.gpus).any(|(a, b)| {
+                f64::from(a.utilization.abs_diff(b.utilization)) > self.cpu_epsilon
+                    || a.mem_used.abs_diff(b.mem_used) > self.mem_epsilon
+            })
+    }
+
+    /// 判断监视进程的运行状态或资源占用是否有变化，阈值与 CPU 和内存相同
+    fn watched_processes_changed(&self, last: &State, state: &State) -> bool {
+        last.watched_processes.len() != state.watched_processes.len()
+            || last
+                .watched_processes
+                .iter()
+                .zip(&state.watched_processes)
+                .any(|(a, b)| {
+                    a.running != b.running
+                        || (a.cpu_usage - b.cpu_usage).abs() > self.cpu_epsilon
+                        || a.mem_used.abs_diff(b.mem_used) > self.mem_epsilon
+                })
+    }
+}
+
+/// 判断健康检查的结果是否有变化，只比较名称和是否成功，忽略耗时
+fn checks_changed(last: &State, state: &State) -> bool {
+    last.checks.len() != state.checks.len()
+        || last
+            .checks
+            .iter()
+            .zip(&state.checks)
+            .any(|(a, b)| a.name != b.name || a.success != b.success)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn filter() -> ReportFilter {
+        let command = Command::parse_from(["agent", "-a", "1", "--max-report-gap", "300"]);
+        let mut filter = ReportFilter::new(&command);
+        filter.record_sent(State::default());
+        filter
+    }
+
+    #[test]
+    fn swap_activity_is_reported() {
+        let filter = filter();
+        assert!(!filter.should_send(&State::default()));
+
+        let swap_in = State {
+            swap_in_rate: filter.speed_epsilon + 1,
+            ..Default::default()
+        };
+        assert!(filter.should_send(&swap_in));
+        let swap_out = State {
+            swap_out_rate: filter.speed_epsilon + 1,
+            ..Default::default()
+        };
+        assert!(filter.should_send(&swap_out));
+    }
+
+    #[test]
+    fn every_metric_is_compared() {
+        let filter = filter();
+        let cases = [
+            State {
+                load1_per_core: 0.1,
+                ..Default::default()
+            },
+            State {
+                load5_per_core: 0.1,
+                ..Default::default()
+            },
+            State {
+                load15_per_core: 0.1,
+                ..Default::default()
+            },
+            State {
+                open_fds: 11,
+                ..Default::default()
+            },
+            State {
+                tcp_connections: 11,
+                ..Default::default()
+            },
+            State {
+                tcp_established: 11,
+                ..Default::default()
+            },
+            State {
+                tcp_listen: 11,
+                ..Default::default()
+            },
+        ];
+        for state in cases {
+            assert!(filter.should_send(&state), "{:?}", state);
+        }
+        // 阈值内的变化不上报
+        let small = State {
+            open_fds: 10,
+            tcp_connections: 5,
+            load1_per_core: 0.01,
+            ..Default::default()
+        };
+        assert!(!filter.should_send(&small));
+    }
+}
@@ -1,8 +1,126 @@
-use common::panda_monitor::{Host, State};
-use sysinfo::{CpuRefreshKind, Disks, Networks, RefreshKind, System};
-use std::{collections::HashSet, ops::Not};
+use common::panda_monitor::{DiskInfo, GpuInfo, Host, NetInterface, ProcessStat, State};
+use std::{
+    collections::{HashMap, HashSet},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use sysinfo::{
+    CpuRefreshKind, Disk, Disks, Networks, ProcessRefreshKind, ProcessesToUpdate, RefreshKind,
+    System, UpdateKind,
+};
 
-use crate::fetch_ip::fetch_geo_ip;
+#[cfg(feature = "geoip")]
+use crate::fetch_ip::{default_providers, GeoIpCache};
+use crate::gpu::GpuCollector;
+use crate::ip_addr::{local_ip, GeoIp, IpMode};
+use crate::net_transfer::TransferTracker;
+
+/// IO 累计值采样，用于磁盘读写和交换空间换入换出
+#[derive(Debug, Clone, Copy)]
+struct IoSample {
+    time: Instant,
+    read_bytes: u64,
+    written_bytes: u64,
+}
+
+impl IoSample {
+    fn now((read_bytes, written_bytes): (u64, u64)) -> Self {
+        Self {
+            time: Instant::now(),
+            read_bytes,
+            written_bytes,
+        }
+    }
+
+    /// 根据上一次采样计算每秒读写字节数，时间间隔为 0 时返回 None
+    fn rates_since(&self, last: &IoSample) -> Option<(u64, u64)> {
+        let secs = self.time.duration_since(last.time).as_secs_f64();
+        if secs <= 0.0 {
+            return None;
+        }
+        Some((
+            (self.read_bytes.saturating_sub(last.read_bytes) as f64 / secs) as u64,
+            (self.written_bytes.saturating_sub(last.written_bytes) as f64 / secs) as u64,
+        ))
+    }
+}
+
+/// cgroup v2 资源限制，未设置限制（`max`）或文件不存在时为 None
+#[derive(Debug, Clone, Copy, Default)]
+struct CgroupLimits {
+    /// 内存上限（字节），来自 `memory.max`
+    memory_max: Option<u64>,
+    /// 交换空间上限（字节），来自 `memory.swap.max`
+    swap_max: Option<u64>,
+    /// 可用 CPU 核心数（可为小数），为 `cpu.max` 的配额除以周期
+    cpu_quota: Option<f64>,
+}
+
+/// TCP 套接字统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpStats {
+    /// 全部套接字数，包含监听套接字和 TIME_WAIT 等状态
+    pub total: u64,
+    /// ESTABLISHED 状态的连接数
+    pub established: u64,
+    /// LISTEN 状态的套接字数
+    pub listen: u64,
+}
+
+/// 同一次刷新得到的主机信息和状态信息
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub host: Host,
+    pub state: State,
+}
+
+/// 磁盘过滤器，用于排除伪文件系统和指定挂载点
+/// 以 `/` 开头的规则视为挂载点前缀，其余视为文件系统类型
+#[derive(Debug, Clone, Default)]
+pub struct DiskFilter {
+    file_systems: Vec<String>,
+    mount_points: Vec<PathBuf>,
+}
+
+impl DiskFilter {
+    pub fn new<I, S>(rules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut filter = Self::default();
+        for rule in rules {
+            let rule = rule.as_ref().trim();
+            if rule.is_empty() {
+                continue;
+            }
+            if rule.starts_with('/') {
+                filter.mount_points.push(PathBuf::from(rule));
+            } else {
+                filter.file_systems.push(rule.to_string());
+            }
+        }
+        filter
+    }
+
+    /// 判断磁盘是否需要排除
+    fn excludes(&self, disk: &Disk) -> bool {
+        let file_system = disk.file_system().to_string_lossy();
+        self.file_systems
+            .iter()
+            .any(|fs| file_system.eq_ignore_ascii_case(fs))
+            || self
+                .mount_points
+                .iter()
+                .any(|prefix| disk.mount_point().starts_with(prefix))
+    }
+}
+
+/// 判断磁盘名称是否为设备路径（如 `/dev/sda1`），`tmpfs` 等虚拟文件系统的名称不是路径，不能用来判断重复挂载
+fn is_block_device(disk: &Disk) -> bool {
+    disk.name().to_string_lossy().starts_with('/')
+}
 
 /// 系统信息收集器
 #[derive(Debug)]
@@ -10,32 +128,281 @@ pub struct SystemInfoCollector {
     sys: System,
     disks: Disks,
     networks: Networks,
+    disk_filter: DiskFilter,
+    ip_mode: IpMode,
+    /// 公网 IP 缓存，仅 `IpMode::Public` 时使用
+    #[cfg(feature = "geoip")]
+    geo_ip_cache: GeoIpCache,
+    /// 上一次的磁盘 IO 采样，用于计算速度
+    last_disk_io: Option<IoSample>,
+    /// 本次上报周期内 CPU 使用率采样的累计值和次数
+    cpu_usage_sum: f64,
+    cpu_usage_count: u32,
+    /// 本次上报周期内 CPU 使用率的平均值
+    cpu_usage: f64,
+    /// 上一次读取 `cpu.stat` 的时间和 `usage_usec`，用于按容器 CPU 配额计算使用率
+    last_cgroup_cpu: Option<(Instant, u64)>,
+    disk_read_speed: u64,
+    disk_write_speed: u64,
+    /// 上一次的交换空间换入换出采样，用于计算速率
+    last_swap_io: Option<IoSample>,
+    swap_in_rate: u64,
+    swap_out_rate: u64,
+    /// 容器资源限制，未开启容器感知时为空
+    cgroup_limits: CgroupLimits,
+    /// 网络总流量跟踪器，未指定状态文件时为空，直接使用网卡计数
+    transfer_tracker: Option<TransferTracker>,
+    /// 随主机信息上报的标签
+    labels: HashMap<String, String>,
+    gpu: GpuCollector,
+    /// 监视的进程名称，为空时不刷新进程列表
+    watched_processes: Vec<String>,
 }
 
 impl SystemInfoCollector {
     /// 创建新的系统信息收集器
-    pub fn new() -> Self {
+    /// `container_aware` 为 true 时读取 cgroup v2 限制，内存、交换空间、CPU 核心数和 CPU 使用率按容器限制上报
+    /// `ip_cache_ttl` 为公网 IP 缓存的有效期，未开启 `geoip` 特性时不使用
+    #[cfg_attr(not(feature = "geoip"), allow(unused_variables))]
+    pub fn new(
+        disk_filter: DiskFilter,
+        ip_mode: IpMode,
+        ip_cache_ttl: Duration,
+        container_aware: bool,
+    ) -> Self {
+        let cgroup_limits = if container_aware {
+            read_cgroup_limits()
+        } else {
+            CgroupLimits::default()
+        };
         Self {
-            sys: System::new_with_specifics(RefreshKind::new().with_cpu(CpuRefreshKind::everything())),
+            sys: System::new_with_specifics(
+                RefreshKind::new().with_cpu(CpuRefreshKind::everything()),
+            ),
             disks: Disks::new(),
             networks: Networks::new_with_refreshed_list(),
+            disk_filter,
+            ip_mode,
+            #[cfg(feature = "geoip")]
+            geo_ip_cache: GeoIpCache::new(ip_cache_ttl, default_providers()),
+            last_disk_io: None,
+            cpu_usage_sum: 0.0,
+            cpu_usage_count: 0,
+            cpu_usage: 0.0,
+            last_cgroup_cpu: None,
+            disk_read_speed: 0,
+            disk_write_speed: 0,
+            last_swap_io: None,
+            swap_in_rate: 0,
+            swap_out_rate: 0,
+            cgroup_limits,
+            transfer_tracker: None,
+            labels: HashMap::new(),
+            gpu: GpuCollector::new(),
+            watched_processes: Vec::new(),
+        }
+    }
+
+    /// 设置监视的进程名称
+    pub fn with_watched_processes(mut self, names: Vec<String>) -> Self {
+        self.watched_processes = names;
+        self
+    }
+
+    /// 设置随主机信息上报的标签
+    pub fn with_labels(mut self, labels: HashMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// 设置获取公网 IP 使用的代理地址，为空时按环境变量使用代理，未开启 `geoip` 特性时不使用
+    #[cfg_attr(not(feature = "geoip"), allow(unused_variables, unused_mut))]
+    pub fn with_ip_proxy(mut self, proxy: Option<String>) -> Self {
+        #[cfg(feature = "geoip")]
+        {
+            self.geo_ip_cache = self.geo_ip_cache.with_proxy(proxy);
         }
+        self
+    }
+
+    /// 使用状态文件持久化网络总流量，探针或主机重启后继续累计
+    pub fn with_transfer_state(mut self, state_file: &str) -> anyhow::Result<Self> {
+        let mut tracker = TransferTracker::load(state_file)?;
+        tracker.update(&self.networks);
+        self.transfer_tracker = Some(tracker);
+        Ok(self)
+    }
+
+    /// 总内存，容器内存上限小于物理内存时使用容器上限
+    fn mem_total(&self) -> u64 {
+        let host = self.sys.total_memory();
+        self.cgroup_limits
+            .memory_max
+            .map_or(host, |limit| limit.min(host))
+    }
+
+    /// 总交换空间，容器交换空间上限小于主机时使用容器上限
+    fn swap_total(&self) -> u64 {
+        let host = self.sys.total_swap();
+        self.cgroup_limits
+            .swap_max
+            .map_or(host, |limit| limit.min(host))
+    }
+
+    /// CPU 核心数，设置了容器 CPU 配额时按配额向上取整
+    fn cpu_cores(&self) -> u64 {
+        let host = self.sys.cpus().len() as u64;
+        self.cgroup_limits
+            .cpu_quota
+            .map_or(host, |quota| (quota.ceil() as u64).clamp(1, host.max(1)))
+    }
+
+    /// 相对容器 CPU 配额的使用率
+    /// 为两次采样间 `cpu.stat` 中 `usage_usec` 的增量除以配额可用的 CPU 时间，
+    /// 未设置配额、首次采样或读取失败时返回 None
+    fn cgroup_cpu_usage(&mut self) -> Option<f64> {
+        // 配额超过主机核心数时按主机核心数计算
+        let host = self.sys.cpus().len() as f64;
+        let quota = self.cgroup_limits.cpu_quota?;
+        let quota = if host > 0.0 { quota.min(host) } else { quota };
+        let now = Instant::now();
+        let usage = read_cgroup_stat("cpu.stat", "usage_usec");
+        let last = std::mem::replace(&mut self.last_cgroup_cpu, usage.map(|usage| (now, usage)));
+        let (last_time, last_usage) = last?;
+        let elapsed = now.duration_since(last_time).as_micros() as f64 * quota;
+        if elapsed <= 0.0 {
+            return None;
+        }
+        let delta = usage?.saturating_sub(last_usage) as f64;
+        Some((delta / elapsed * 100.0).min(100.0))
+    }
+
+    /// 已用内存和可用内存
+    /// 设置了容器内存上限时使用 `memory.current`（包含页缓存），否则使用主机数据
+    fn memory_usage(&self) -> (u64, u64) {
+        if self.cgroup_limits.memory_max.is_some() {
+            if let Some(current) = read_cgroup_value("memory.current") {
+                return (current, self.mem_total().saturating_sub(current));
+            }
+        }
+        (self.sys.used_memory(), self.sys.available_memory())
+    }
+
+    /// 扣除可回收缓存后的已用内存
+    /// 设置了容器内存上限时为 `memory.current` 减去 `memory.stat` 中的 `inactive_file`，
+    /// 否则为总内存减去可用内存，可用内存未知（为 0）时返回 `mem_used`
+    fn mem_used_real(&self, mem_used: u64, mem_available: u64) -> u64 {
+        if self.cgroup_limits.memory_max.is_some() {
+            if let (Some(current), Some(inactive_file)) = (
+                read_cgroup_value("memory.current"),
+                read_cgroup_stat("memory.stat", "inactive_file"),
+            ) {
+                return current.saturating_sub(inactive_file);
+            }
+        }
+        if mem_available == 0 {
+            return mem_used;
+        }
+        self.mem_total().saturating_sub(mem_available)
+    }
+
+    /// 已用交换空间，设置了容器交换空间上限时使用 `memory.swap.current`
+    fn swap_used(&self) -> u64 {
+        if self.cgroup_limits.swap_max.is_some() {
+            if let Some(current) = read_cgroup_value("memory.swap.current") {
+                return current;
+            }
+        }
+        self.sys.used_swap()
+    }
+
+    /// 采样一次 CPU 使用率，下次 `refresh` 时上报本周期所有采样的平均值
+    /// 设置了容器 CPU 配额时按配额计算，否则使用主机 CPU 使用率
+    pub fn sample_cpu(&mut self) {
+        self.sys.refresh_cpu_usage();
+        let usage = self
+            .cgroup_cpu_usage()
+            .unwrap_or_else(|| f64::from(self.sys.global_cpu_usage()));
+        self.cpu_usage_sum += usage;
+        self.cpu_usage_count += 1;
     }
 
     /// 刷新系统组件信息
     pub fn refresh(&mut self) {
         self.disks.refresh_list();
         self.sys.refresh_memory();
-        self.sys.refresh_cpu_usage();
+        self.sample_cpu();
+        self.cpu_usage = self.cpu_usage_sum / f64::from(self.cpu_usage_count);
+        self.cpu_usage_sum = 0.0;
+        self.cpu_usage_count = 0;
+        self.networks.refresh();
+        if let Some(tracker) = self.transfer_tracker.as_mut() {
+            collect_optional("网络总流量", || tracker.update(&self.networks));
+        }
+        self.refresh_disk_io();
+        self.refresh_swap_io();
+        if !self.watched_processes.is_empty() {
+            // 进程的 CPU 使用率由两次刷新之间的 CPU 时间计算，首次刷新时为 0
+            self.sys.refresh_processes_specifics(
+                ProcessesToUpdate::All,
+                true,
+                ProcessRefreshKind::new()
+                    .with_cpu()
+                    .with_memory()
+                    .with_exe(UpdateKind::OnlyIfNotSet),
+            );
+        }
+    }
+
+    /// 刷新磁盘 IO 速度
+    /// 速度由两次采样的累计值之差计算，首次采样时速度为 0
+    fn refresh_disk_io(&mut self) {
+        let Some(counters) = collect_optional("磁盘 IO", read_disk_io) else {
+            return;
+        };
+        let sample = IoSample::now(counters);
+        if let Some(rates) = self.last_disk_io.and_then(|last| sample.rates_since(&last)) {
+            (self.disk_read_speed, self.disk_write_speed) = rates;
+        }
+        self.last_disk_io = Some(sample);
+    }
+
+    /// 刷新交换空间换入换出速率，计算方式与磁盘 IO 相同
+    fn refresh_swap_io(&mut self) {
+        let Some(counters) = collect_optional("交换空间换入换出", read_swap_io) else {
+            return;
+        };
+        let sample = IoSample::now(counters);
+        if let Some(rates) = self.last_swap_io.and_then(|last| sample.rates_since(&last)) {
+            (self.swap_in_rate, self.swap_out_rate) = rates;
+        }
+        self.last_swap_io = Some(sample);
+    }
+
+    /// 获取未被过滤的磁盘，总量和使用量使用相同的过滤规则
+    /// 同一块设备挂载在多个位置时（如容器中的绑定挂载）只保留第一个挂载点，避免重复统计容量
+    fn filtered_disks(&self) -> impl Iterator<Item = &Disk> {
+        let mut devices = HashSet::new();
+        self.disks.list().iter().filter(move |disk| {
+            !self.disk_filter.excludes(disk)
+                && (!is_block_device(disk) || devices.insert(disk.name()))
+        })
+    }
+
+    /// 刷新一次系统组件信息，并基于同一份数据获取主机信息和状态信息
+    /// CPU 使用率和磁盘 IO 速度按与上一次刷新之间的差值计算，首次调用前应先 `refresh` 一次
+    pub async fn snapshot(&mut self) -> Snapshot {
+        self.refresh();
+        Snapshot {
+            host: self.get_host_info().await,
+            state: self.get_system_state(),
+        }
     }
 
     /// 获取服务器主机信息
     pub async fn get_host_info(&self) -> Host {
         let disk_total = self
-            .disks
-            .list()
-            .iter()
-            .filter(|disk| disk.file_system().eq_ignore_ascii_case("overlay").not())
+            .filtered_disks()
             .map(|disk| disk.total_space())
             .sum::<u64>();
         let cpu = self
@@ -47,70 +414,499 @@ impl SystemInfoCollector {
             .into_iter()
             .map(|cpu_brand| cpu_brand.to_string())
             .collect::<Vec<String>>();
-        let geo_ip = fetch_geo_ip().await;
+        let geo_ip = self.get_ip().await;
         Host {
             os_name: System::name().unwrap_or_default().trim().to_string(),
             distribution_id: System::distribution_id(),
             os_version: System::os_version().unwrap_or_default(),
             cpu,
-            cpu_cores: self.sys.cpus().len() as u64,
+            cpu_cores: self.cpu_cores(),
             kernel_version: System::kernel_version().unwrap_or_default(),
-            mem_total: self.sys.total_memory(),
+            mem_total: self.mem_total(),
             disk_total,
-            swap_total: self.sys.total_swap(),
+            swap_total: self.swap_total(),
             arch: System::cpu_arch().unwrap_or_default(),
             boot_time: System::boot_time(),
             ipv4: geo_ip.ipv4,
             ipv6: geo_ip.ipv6,
+            disks: collect_optional("分区详情", || self.get_disk_details()),
+            net_interfaces: collect_optional("网卡信息", || self.get_net_interfaces()),
+            labels: self.labels.clone(),
         }
     }
 
+    /// 获取各网卡的链路速率、启用状态和 MAC 地址，按名称排序
+    pub fn get_net_interfaces(&self) -> Vec<NetInterface> {
+        let mut interfaces = self
+            .networks
+            .list()
+            .iter()
+            .map(|(name, data)| {
+                let (speed_mbps, is_up) = read_link_status(name);
+                NetInterface {
+                    name: name.clone(),
+                    speed_mbps,
+                    is_up,
+                    mac: data.mac_address().to_string(),
+                }
+            })
+            .collect::<Vec<_>>();
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+        interfaces
+    }
+
+    /// 按配置的方式获取 IP 地址
+    pub async fn get_ip(&self) -> GeoIp {
+        match self.ip_mode {
+            #[cfg(feature = "geoip")]
+            IpMode::Public => self.geo_ip_cache.get().await,
+            #[cfg(not(feature = "geoip"))]
+            IpMode::Public => GeoIp::default(),
+            IpMode::Local => local_ip(&self.networks),
+        }
+    }
+
+    /// 获取各分区的磁盘使用情况，使用与总量相同的过滤规则
+    pub fn get_disk_details(&self) -> Vec<DiskInfo> {
+        self.filtered_disks()
+            .map(|disk| {
+                let (inodes_total, inodes_used) = read_inode_usage(disk.mount_point());
+                DiskInfo {
+                    mount_point: disk.mount_point().to_string_lossy().into_owned(),
+                    fs_type: disk.file_system().to_string_lossy().into_owned(),
+                    total: disk.total_space(),
+                    used: disk.total_space().saturating_sub(disk.available_space()),
+                    inodes_total,
+                    inodes_used,
+                }
+            })
+            .collect()
+    }
+
     /// 获取服务器状态信息
+    ///
+    /// 内存有两个已用值：`mem_used` 为 sysinfo 或容器 `memory.current` 的原始值，
+    /// 在容器中以及部分平台上包含页缓存，可能远高于 `free`、`htop` 显示的 used；
+    /// `mem_used_real` 扣除了可回收的缓存（总内存减去可用内存），与这些工具一致，更适合用于告警。
     pub fn get_system_state(&self) -> State {
         let disk_used = self
-            .disks
-            .list()
-            .iter()
-            .filter(|disk| disk.file_system().eq_ignore_ascii_case("overlay").not())
+            .filtered_disks()
             .map(|disk| disk.total_space() - disk.available_space())
             .sum::<u64>();
-        let net_in_transfer = self
-            .networks
-            .list()
-            .iter()
-            .map(|(_, net)| net.total_received())
-            .sum::<u64>();
-        let net_out_transfer = self
-            .networks
-            .list()
-            .iter()
-            .map(|(_, net)| net.total_transmitted())
-            .sum::<u64>();
+        let (net_in_transfer, net_out_transfer) = match &self.transfer_tracker {
+            Some(tracker) => tracker.totals(),
+            None => (
+                self.networks
+                    .list()
+                    .values()
+                    .map(|net| net.total_received())
+                    .sum::<u64>(),
+                self.networks
+                    .list()
+                    .values()
+                    .map(|net| net.total_transmitted())
+                    .sum::<u64>(),
+            ),
+        };
         let net_in_speed = self
             .networks
             .list()
-            .iter()
-            .map(|(_, net)| net.received())
+            .values()
+            .map(|net| net.received())
             .sum::<u64>();
         let net_out_speed = self
             .networks
             .list()
-            .iter()
-            .map(|(_, net)| net.transmitted())
+            .values()
+            .map(|net| net.transmitted())
             .sum::<u64>();
+        let load_average = System::load_average();
+        let cpu_cores = self.cpu_cores();
+        // 核心数未知时无法归一化，发送 0
+        let per_core = |load: f64| {
+            if cpu_cores == 0 {
+                0.0
+            } else {
+                load / cpu_cores as f64
+            }
+        };
+        let (open_fds, tcp) =
+            collect_optional("文件描述符和 TCP 连接数", || self.get_fd_stats());
+        let (mem_used, mem_available) = self.memory_usage();
+        let mem_used_real = self.mem_used_real(mem_used, mem_available);
 
         State {
-            cpu_usage: self.sys.global_cpu_usage() as f64,
-            mem_used: self.sys.used_memory(),
-            swap_used: self.sys.used_swap(),
+            cpu_usage: self.cpu_usage,
+            mem_used,
+            mem_available,
+            mem_used_real,
+            swap_used: self.swap_used(),
             disk_used,
             net_in_transfer,
             net_out_transfer,
             net_in_speed,
             net_out_speed,
-            load1: System::load_average().one,
-            load5: System::load_average().five,
-            load15: System::load_average().fifteen,
+            load1: load_average.one,
+            load5: load_average.five,
+            load15: load_average.fifteen,
+            load1_per_core: per_core(load_average.one),
+            load5_per_core: per_core(load_average.five),
+            load15_per_core: per_core(load_average.fifteen),
+            disk_read_speed: self.disk_read_speed,
+            disk_write_speed: self.disk_write_speed,
+            swap_in_rate: self.swap_in_rate,
+            swap_out_rate: self.swap_out_rate,
+            open_fds,
+            tcp_connections: tcp.total,
+            tcp_established: tcp.established,
+            tcp_listen: tcp.listen,
+            gpus: collect_optional("GPU 信息", || self.get_gpu_info()),
+            watched_processes: collect_optional("进程信息", || self.get_watched_processes()),
+            ..Default::default()
+        }
+    }
+
+    /// 按名称汇总监视进程的 CPU 使用率和常驻内存，顺序与参数一致
+    /// Linux 上线程也会作为进程列出，统计时跳过，避免重复计算
+    pub fn get_watched_processes(&self) -> Vec<ProcessStat> {
+        self.watched_processes
+            .iter()
+            .map(|name| {
+                let mut stat = ProcessStat {
+                    name: name.clone(),
+                    ..Default::default()
+                };
+                for process in self.sys.processes().values() {
+                    let matched = process.name() == name.as_str()
+                        || process
+                            .exe()
+                            .and_then(|exe| exe.file_name())
+                            .is_some_and(|file_name| file_name == name.as_str());
+                    if process.thread_kind().is_some() || !matched {
+                        continue;
+                    }
+                    stat.count += 1;
+                    stat.cpu_usage += f64::from(process.cpu_usage());
+                    stat.mem_used += process.memory();
+                }
+                stat.running = stat.count > 0;
+                stat
+            })
+            .collect()
+    }
+
+    /// 获取各 GPU 的使用率、显存和温度，未开启 `gpu-nvidia` 特性或没有 GPU 时为空
+    pub fn get_gpu_info(&self) -> Vec<GpuInfo> {
+        self.gpu.collect()
+    }
+
+    /// 获取已分配的文件描述符数量和 TCP 连接数
+    /// 只读取 procfs 中的计数，开销足够小，可以在每秒的上报循环中调用
+    pub fn get_fd_stats(&self) -> (u64, TcpStats) {
+        (read_open_fds(), count_tcp_sockets())
+    }
+}
+
+/// 执行可选的采集项，采集项 panic 时输出警告并返回默认值
+/// 个别平台上某项采集失败不影响核心指标上报
+fn collect_optional<T: Default>(name: &str, collect: impl FnOnce() -> T) -> T {
+    let collect = || {
+        #[cfg(test)]
+        tests::inject_failure(name);
+        collect()
+    };
+    panic::catch_unwind(AssertUnwindSafe(collect)).unwrap_or_else(|payload| {
+        let reason = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("未知错误");
+        tracing::warn!("采集 {} 失败，本次使用默认值: {}", name, reason);
+        T::default()
+    })
+}
+
+/// 通过 statvfs 读取挂载点的 inode 总数和已用数量，读取失败时返回 0
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // fsfilcnt_t 在 Linux 上为 u64，在 macOS 等平台上为 u32
+fn read_inode_usage(mount_point: &Path) -> (u64, u64) {
+    match nix::sys::statvfs::statvfs(mount_point) {
+        Ok(stat) => {
+            let total = stat.files() as u64;
+            (total, total.saturating_sub(stat.files_free() as u64))
         }
+        Err(e) => {
+            tracing::debug!("读取 {} 的 inode 信息失败: {}", mount_point.display(), e);
+            (0, 0)
+        }
+    }
+}
+
+/// 非 Unix 平台没有 inode，返回 0
+#[cfg(not(unix))]
+fn read_inode_usage(_mount_point: &Path) -> (u64, u64) {
+    (0, 0)
+}
+
+// 常量定义
+const CGROUP_ROOT: &str = "/sys/fs/cgroup"; // cgroup v2 挂载点，容器内为当前容器的 cgroup
+
+/// 读取 cgroup v2 的资源限制
+#[cfg(target_os = "linux")]
+fn read_cgroup_limits() -> CgroupLimits {
+    CgroupLimits {
+        memory_max: read_cgroup_value("memory.max"),
+        swap_max: read_cgroup_value("memory.swap.max"),
+        cpu_quota: read_cgroup_cpu_quota(),
+    }
+}
+
+/// 非 Linux 平台没有 cgroup，使用主机数据
+#[cfg(not(target_os = "linux"))]
+fn read_cgroup_limits() -> CgroupLimits {
+    CgroupLimits::default()
+}
+
+/// 读取单个数值的 cgroup 文件，值为 `max` 或文件不存在时返回 None
+fn read_cgroup_value(name: &str) -> Option<u64> {
+    std::fs::read_to_string(std::path::Path::new(CGROUP_ROOT).join(name))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// 读取 cgroup 统计文件（如 `memory.stat`）中指定键的值，文件每行格式为 `$KEY $VALUE`
+fn read_cgroup_stat(name: &str, key: &str) -> Option<u64> {
+    std::fs::read_to_string(std::path::Path::new(CGROUP_ROOT).join(name))
+        .ok()?
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix(key)?
+                .strip_prefix(' ')?
+                .trim()
+                .parse()
+                .ok()
+        })
+}
+
+/// 根据 `cpu.max` 计算可用 CPU 核心数
+/// 文件格式为 `$MAX $PERIOD`，`$MAX` 为 `max` 表示不限制
+fn read_cgroup_cpu_quota() -> Option<f64> {
+    let content =
+        std::fs::read_to_string(std::path::Path::new(CGROUP_ROOT).join("cpu.max")).ok()?;
+    let mut fields = content.split_whitespace();
+    let quota: u64 = fields.next()?.parse().ok()?;
+    let period: u64 = fields.next()?.parse().ok()?;
+    if period == 0 {
+        return None;
     }
-} 
\ No newline at end of file
+    Some(quota as f64 / period as f64)
+}
+
+/// 读取网卡的链路速率（Mbps）和启用状态
+/// 速率来自 `/sys/class/net/<网卡>/speed`，虚拟网卡读取失败或为 -1 时为 0；
+/// 状态优先使用 `operstate`，为 `unknown`（如 lo）时按 `flags` 中的 IFF_UP 判断
+#[cfg(target_os = "linux")]
+fn read_link_status(name: &str) -> (u64, bool) {
+    const IFF_UP: u64 = 0x1;
+
+    let dir = std::path::Path::new("/sys/class/net").join(name);
+    let read = |file: &str| std::fs::read_to_string(dir.join(file)).ok();
+    let speed = read("speed")
+        .and_then(|speed| speed.trim().parse::<i64>().ok())
+        .filter(|speed| *speed > 0)
+        .map_or(0, |speed| speed as u64);
+    let is_up = match read("operstate").as_deref().map(str::trim) {
+        Some("up") => true,
+        Some("unknown") | None => read("flags")
+            .and_then(|flags| u64::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+            .is_some_and(|flags| flags & IFF_UP != 0),
+        Some(_) => false,
+    };
+    (speed, is_up)
+}
+
+/// 非 Linux 平台无法读取链路状态，速率为 0，视为已启用
+#[cfg(not(target_os = "linux"))]
+fn read_link_status(_name: &str) -> (u64, bool) {
+    (0, true)
+}
+
+/// 读取系统已分配的文件描述符数量
+/// `/proc/sys/fs/file-nr` 的第一列为已分配数量
+#[cfg(target_os = "linux")]
+fn read_open_fds() -> u64 {
+    std::fs::read_to_string("/proc/sys/fs/file-nr")
+        .ok()
+        .and_then(|content| content.split_whitespace().next()?.parse().ok())
+        .unwrap_or(0)
+}
+
+/// 统计 `/proc/net/tcp` 与 `/proc/net/tcp6` 中的套接字数
+/// 每个文件第一行为表头，其余每行对应一个套接字，第 4 列为十六进制的连接状态，不解析地址
+#[cfg(target_os = "linux")]
+fn count_tcp_sockets() -> TcpStats {
+    // 内核 tcp_states.h 中的状态值
+    const TCP_ESTABLISHED: &str = "01";
+    const TCP_LISTEN: &str = "0A";
+
+    let mut stats = TcpStats::default();
+    for content in ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+    {
+        for line in content.lines().skip(1) {
+            stats.total += 1;
+            match line.split_whitespace().nth(3) {
+                Some(TCP_ESTABLISHED) => stats.established += 1,
+                Some(TCP_LISTEN) => stats.listen += 1,
+                _ => {}
+            }
+        }
+    }
+    stats
+}
+
+/// 非 Linux 平台暂不支持文件描述符统计
+#[cfg(not(target_os = "linux"))]
+fn read_open_fds() -> u64 {
+    0
+}
+
+/// 非 Linux 平台暂不支持 TCP 连接数统计，首次调用时输出一次提示
+#[cfg(not(target_os = "linux"))]
+fn count_tcp_sockets() -> TcpStats {
+    static UNSUPPORTED: std::sync::Once = std::sync::Once::new();
+    UNSUPPORTED.call_once(|| tracing::info!("当前平台不支持 TCP 连接数统计，上报为 0"));
+    TcpStats::default()
+}
+
+/// 读取所有物理磁盘的累计读写字节数
+/// 数据来自 `/proc/diskstats`，只统计 `/sys/block` 下的整盘设备，
+/// 跳过分区、loop、ram 以及 dm/md 等虚拟设备，避免重复计算
+#[cfg(target_os = "linux")]
+fn read_disk_io() -> Option<(u64, u64)> {
+    // diskstats 中的扇区大小固定为 512 字节
+    const SECTOR_SIZE: u64 = 512;
+
+    let content = std::fs::read_to_string("/proc/diskstats").ok()?;
+    let mut read_bytes = 0;
+    let mut written_bytes = 0;
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 {
+            continue;
+        }
+        let name = fields[2];
+        if ["loop", "ram", "dm-", "md"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+            || !std::path::Path::new("/sys/block").join(name).exists()
+        {
+            continue;
+        }
+        read_bytes += fields[5].parse::<u64>().unwrap_or(0) * SECTOR_SIZE;
+        written_bytes += fields[9].parse::<u64>().unwrap_or(0) * SECTOR_SIZE;
+    }
+    Some((read_bytes, written_bytes))
+}
+
+/// 非 Linux 平台暂不支持磁盘 IO 统计
+#[cfg(not(target_os = "linux"))]
+fn read_disk_io() -> Option<(u64, u64)> {
+    None
+}
+
+/// 读取交换空间累计换入和换出的字节数
+/// 数据来自 `/proc/vmstat` 的 `pswpin`/`pswpout`，单位为页
+#[cfg(target_os = "linux")]
+fn read_swap_io() -> Option<(u64, u64)> {
+    let content = std::fs::read_to_string("/proc/vmstat").ok()?;
+    let mut swap_in = None;
+    let mut swap_out = None;
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(value)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        match name {
+            "pswpin" => swap_in = value.parse::<u64>().ok(),
+            "pswpout" => swap_out = value.parse::<u64>().ok(),
+            _ => {}
+        }
+    }
+    let page_size = page_size();
+    Some((swap_in? * page_size, swap_out? * page_size))
+}
+
+/// 系统页大小，获取失败时按主流架构的 4KiB 计算
+/// aarch64 等架构可能使用 16KiB 或 64KiB 的页
+#[cfg(target_os = "linux")]
+fn page_size() -> u64 {
+    // 常量定义
+    const DEFAULT_PAGE_SIZE: u64 = 4096; // 获取失败时使用的页大小
+
+    match nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE) {
+        Ok(Some(size)) if size > 0 => size as u64,
+        _ => DEFAULT_PAGE_SIZE,
+    }
+}
+
+/// 非 Linux 平台暂不支持交换空间换入换出统计
+#[cfg(not(target_os = "linux"))]
+fn read_swap_io() -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        /// 模拟失败的采集项名称
+        static FAILING_COLLECTOR: Cell<Option<&'static str>> = const { Cell::new(None) };
+    }
+
+    /// 当前采集项被设置为模拟失败时 panic
+    pub(super) fn inject_failure(name: &str) {
+        if FAILING_COLLECTOR.get() == Some(name) {
+            panic!("模拟 {} 采集失败", name);
+        }
+    }
+
+    #[test]
+    fn collect_optional_returns_default_on_panic() {
+        assert_eq!(collect_optional("正常", || 42u64), 42);
+        assert_eq!(
+            collect_optional("固定消息", || -> u64 { panic!("boom") }),
+            0
+        );
+        let reason = String::from("boom");
+        let gpus = collect_optional("格式化消息", || -> Vec<GpuInfo> {
+            panic!("{}", reason)
+        });
+        assert!(gpus.is_empty());
+    }
+
+    #[test]
+    fn failing_collector_keeps_other_metrics() {
+        let mut collector =
+            SystemInfoCollector::new(DiskFilter::default(), IpMode::Local, Duration::ZERO, false);
+        collector.refresh();
+
+        FAILING_COLLECTOR.set(Some("文件描述符和 TCP 连接数"));
+        let state = collector.get_system_state();
+        FAILING_COLLECTOR.set(None);
+
+        assert_eq!(state.open_fds, 0);
+        assert_eq!(state.tcp_connections, 0);
+        assert_eq!(state.tcp_listen, 0);
+        assert!(state.mem_used > 0);
+        assert!(state.mem_available > 0);
+        assert!(state.cpu_usage.is_finite());
+        #[cfg(target_os = "linux")]
+        assert!(collector.get_system_state().open_fds > 0);
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// 指数退避策略
+/// 第 n 次重试的等待时间在 `base` 到 `min(base * 2^n, max)` 之间随机选取，上限随重试次数翻倍，
+/// 等待时间不短于 `base`，且每次重试（包括达到上限后）都有随机抖动，避免大量探针在服务端重启后同时重连
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self { base, max }
+    }
+
+    /// 计算第 `attempt` 次重试（从 1 开始）前的等待时间
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let upper = self.base.saturating_mul(1 << attempt.min(31)).min(self.max);
+        let spread = upper.saturating_sub(self.base);
+        self.base + spread.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 常量定义
+    const AGENTS: usize = 50; // 同时重连的探针数量
+
+    #[test]
+    fn concurrent_agents_are_jittered() {
+        let base = Duration::from_secs(2);
+        let max = Duration::from_secs(30);
+        let backoff = Backoff::new(base, max);
+
+        for attempt in [1, 2, 3, 10, u32::MAX] {
+            let delays: Vec<Duration> = (0..AGENTS).map(|_| backoff.delay(attempt)).collect();
+            assert!(
+                delays.iter().all(|delay| (base..=max).contains(delay)),
+                "第 {} 次重试的等待时间超出范围: {:?}",
+                attempt,
+                delays
+            );
+            assert!(
+                delays.iter().any(|delay| *delay != delays[0]),
+                "第 {} 次重试的等待时间没有抖动",
+                attempt
+            );
+        }
+    }
+
+    #[test]
+    fn upper_bound_doubles_until_max() {
+        let backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+
+        for _ in 0..AGENTS {
+            assert!(backoff.delay(1) <= Duration::from_secs(2));
+            assert!(backoff.delay(2) <= Duration::from_secs(4));
+            assert!(backoff.delay(5) <= Duration::from_secs(10));
+        }
+    }
+
+    #[test]
+    fn equal_base_and_max_is_constant() {
+        let delay = Duration::from_secs(5);
+        let backoff = Backoff::new(delay, delay);
+
+        assert!((1..=AGENTS as u32).all(|attempt| backoff.delay(attempt) == delay));
+    }
+}
@@ -1,44 +1,76 @@
 use std::{net::IpAddr, str::FromStr, time::Duration};
 
-use reqwest::{Client, ClientBuilder};
+use reqwest::{header::RETRY_AFTER, Client, ClientBuilder, Proxy, Response, StatusCode};
 use serde::de::DeserializeOwned;
 
+// 常量定义
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5); // 重试前最长等待时间，Retry-After 超过该值时不再重试
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500); // 连接超时时间
+
 pub struct HttpUtil {
     client: Client,
     ipv4_client: Client,
     ipv6_client: Client,
+    /// 请求被限流、服务端出错或超时后的重试次数，默认为 0
+    retries: u32,
+    /// 响应没有 Retry-After 时的重试间隔
+    retry_delay: Duration,
 }
 
 impl HttpUtil {
-    pub fn new() -> Self {
+    /// 创建 HTTP 客户端
+    /// 未指定 `proxy` 时按 `HTTP_PROXY`、`HTTPS_PROXY`、`ALL_PROXY` 和 `NO_PROXY` 环境变量使用代理，
+    /// 指定后所有请求都经过该代理，忽略环境变量。使用代理时只请求 IPv4 或 IPv6 地址的客户端
+    /// 只限制到代理的连接，目标地址的协议由代理决定。
+    pub fn new(proxy: Option<&str>) -> anyhow::Result<Self> {
         let ipv4_addr = IpAddr::from_str("0.0.0.0").unwrap();
         let ipv6_addr = IpAddr::from_str("::").unwrap();
-        let http_util = HttpUtil {
-            client: ClientBuilder::new()
-                .connect_timeout(Duration::from_millis(500))
+        let builder = || -> anyhow::Result<ClientBuilder> {
+            let mut builder = ClientBuilder::new().connect_timeout(CONNECT_TIMEOUT);
+            if let Some(proxy) = proxy {
+                builder = builder.proxy(
+                    Proxy::all(proxy)
+                        .map_err(|e| anyhow::anyhow!("无效的代理地址 {}: {}", proxy, e))?,
+                );
+            }
+            Ok(builder)
+        };
+        Ok(HttpUtil {
+            client: builder()?
                 .build()
-                .expect("Init Http Client Failed"),
-            ipv4_client: ClientBuilder::new()
-                .connect_timeout(Duration::from_millis(500))
+                .map_err(|e| anyhow::anyhow!("创建 HTTP 客户端失败: {}", e))?,
+            ipv4_client: builder()?
                 .local_address(ipv4_addr)
                 .build()
-                .expect("Init only send ipv4 Http Client Failed"),
-            ipv6_client: ClientBuilder::new()
-                .connect_timeout(Duration::from_millis(500))
+                .map_err(|e| anyhow::anyhow!("创建 IPv4 HTTP 客户端失败: {}", e))?,
+            ipv6_client: builder()?
                 .local_address(ipv6_addr)
                 .build()
-                .expect("Init only send ipv6 Http Client Failed"),
-        };
-        http_util
+                .map_err(|e| anyhow::anyhow!("创建 IPv6 HTTP 客户端失败: {}", e))?,
+            retries: 0,
+            retry_delay: Duration::ZERO,
+        })
+    }
+
+    /// 设置获取 JSON 时的重试次数和间隔，响应带有 Retry-After 时按其等待
+    pub fn with_retry(mut self, retries: u32, retry_delay: Duration) -> Self {
+        self.retries = retries;
+        self.retry_delay = retry_delay;
+        self
     }
+
     /// 发送 get 请求
     pub async fn send_get<T>(&self, url: &str) -> anyhow::Result<T>
     where
         T: DeserializeOwned,
     {
+        self.get_json(&self.client, url).await
+    }
+
+    /// 发送 get 请求，只返回响应状态码
+    pub async fn get_status(&self, url: &str) -> anyhow::Result<reqwest::StatusCode> {
         let resp = self.client.get(url).send().await?;
-        let json = resp.json::<T>().await?;
-        Ok(json)
+        Ok(resp.status())
     }
 
     /// 发送 get 请求，仅请求 ipv4 地址
@@ -46,9 +78,7 @@ impl HttpUtil {
     where
         T: DeserializeOwned,
     {
-        let resp = self.ipv4_client.get(url).send().await?;
-        let json = resp.json::<T>().await?;
-        Ok(json)
+        self.get_json(&self.ipv4_client, url).await
     }
 
     /// 发送 get 请求，仅请求 ipv6 地址
@@ -56,8 +86,47 @@ impl HttpUtil {
     where
         T: DeserializeOwned,
     {
-        let resp = self.ipv6_client.get(url).send().await?;
-        let json = resp.json::<T>().await?;
-        Ok(json)
+        self.get_json(&self.ipv6_client, url).await
     }
-}
\ No newline at end of file
+
+    /// 获取 JSON，被限流（429）、服务端出错（5xx）或超时时按配置重试
+    async fn get_json<T>(&self, client: &Client, url: &str) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let mut attempts = 0;
+        loop {
+            let result = client.get(url).send().await;
+            let retry_in = match &result {
+                Ok(resp)
+                    if resp.status() == StatusCode::TOO_MANY_REQUESTS
+                        || resp.status().is_server_error() =>
+                {
+                    retry_after(resp).or(Some(self.retry_delay))
+                }
+                Err(e) if e.is_timeout() => Some(self.retry_delay),
+                _ => None,
+            };
+            match retry_in {
+                Some(delay) if attempts < self.retries && delay <= MAX_RETRY_DELAY => {
+                    attempts += 1;
+                    tracing::debug!("请求 {} 失败，{}ms 后重试", url, delay.as_millis());
+                    tokio::time::sleep(delay).await;
+                }
+                _ => return Ok(result?.error_for_status()?.json::<T>().await?),
+            }
+        }
+    }
+}
+
+/// 读取响应中以秒数表示的 Retry-After
+fn retry_after(resp: &Response) -> Option<Duration> {
+    resp.headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
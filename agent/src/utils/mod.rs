@@ -1 +1,3 @@
+pub mod backoff;
+#[cfg(feature = "geoip")]
 pub mod http_util;
@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use common::panda_monitor::{Host, State};
+use serde::Serialize;
+
+use crate::rpc_service::{now_secs, ServerKey};
+
+// 常量定义
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10); // Webhook 请求超时时间
+const OFFLINE_REASON: &str = "offline"; // 探针离线导致告警解除时通知中的原因
+
+/// 告警规则可用的指标，取值与状态上报中的字段一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertMetric {
+    /// CPU 使用率（%）
+    Cpu,
+    /// 已用内存（字节）
+    MemUsed,
+    /// 已用内存占总内存的百分比（%），总内存来自主机信息
+    MemUsedPercent,
+    /// 可用内存（字节）
+    MemAvailable,
+    /// 扣除可回收缓存后的已用内存（字节）
+    MemUsedReal,
+    /// 已用交换空间（字节）
+    SwapUsed,
+    /// 已用磁盘空间（字节）
+    DiskUsed,
+    /// 已用磁盘空间占总磁盘空间的百分比（%），总磁盘空间来自主机信息
+    DiskUsedPercent,
+    /// 1 分钟负载
+    Load1,
+    /// 按核心数归一化的 1 分钟负载
+    Load1PerCore,
+}
+
+impl AlertMetric {
+    /// 规则中使用的指标名称
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Cpu => "cpu",
+            Self::MemUsed => "mem_used",
+            Self::MemUsedPercent => "mem_used_percent",
+            Self::MemAvailable => "mem_available",
+            Self::MemUsedReal => "mem_used_real",
+            Self::SwapUsed => "swap_used",
+            Self::DiskUsed => "disk_used",
+            Self::DiskUsedPercent => "disk_used_percent",
+            Self::Load1 => "load1",
+            Self::Load1PerCore => "load1_per_core",
+        }
+    }
+
+    /// 从状态中读取指标值，百分比指标在尚未收到主机信息时返回 None
+    fn value(self, state: &State, totals: HostTotals) -> Option<f64> {
+        let percent =
+            |used: u64, total: u64| (total > 0).then(|| used as f64 / total as f64 * 100.0);
+        match self {
+            Self::Cpu => Some(state.cpu_usage),
+            Self::MemUsed => Some(state.mem_used as f64),
+            Self::MemUsedPercent => percent(state.mem_used, totals.mem_total),
+            Self::MemAvailable => Some(state.mem_available as f64),
+            Self::MemUsedReal => Some(state.mem_used_real as f64),
+            Self::SwapUsed => Some(state.swap_used as f64),
+            Self::DiskUsed => Some(state.disk_used as f64),
+            Self::DiskUsedPercent => percent(state.disk_used, totals.disk_total),
+            Self::Load1 => Some(state.load1),
+            Self::Load1PerCore => Some(state.load1_per_core),
+        }
+    }
+}
+
+impl FromStr for AlertMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(Self::Cpu),
+            "mem_used" => Ok(Self::MemUsed),
+            "mem_used_percent" => Ok(Self::MemUsedPercent),
+            "mem_available" => Ok(Self::MemAvailable),
+            "mem_used_real" => Ok(Self::MemUsedReal),
+            "swap_used" => Ok(Self::SwapUsed),
+            "disk_used" => Ok(Self::DiskUsed),
+            "disk_used_percent" => Ok(Self::DiskUsedPercent),
+            "load1" => Ok(Self::Load1),
+            "load1_per_core" => Ok(Self::Load1PerCore),
+            _ => Err(format!(
+                "未知的指标 {}，可选值为 cpu、mem_used、mem_used_percent、mem_available、mem_used_real、swap_used、disk_used、disk_used_percent、load1、load1_per_core",
+                s
+            )),
+        }
+    }
+}
+
+/// 比较运算符
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertOp {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl AlertOp {
+    // 两个字符的运算符排在前面，避免 `>=` 被识别为 `>`
+    const ALL: [Self; 4] = [Self::Ge, Self::Le, Self::Gt, Self::Lt];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+        }
+    }
+
+    fn compare(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Gt => value > threshold,
+            Self::Ge => value >= threshold,
+            Self::Lt => value < threshold,
+            Self::Le => value <= threshold,
+        }
+    }
+}
+
+/// 告警规则，格式为 `<指标><运算符><阈值>[:<持续秒数>]`，例如 `cpu>90:60`
+/// 指标持续满足条件 `for_secs` 秒后触发告警，持续不满足同样时长后解除，避免指标抖动时频繁通知
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    pub metric: AlertMetric,
+    pub op: AlertOp,
+    pub threshold: f64,
+    pub for_secs: u64,
+}
+
+impl FromStr for AlertRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (condition, for_secs) = match s.split_once(':') {
+            Some((condition, secs)) => (
+                condition,
+                secs.parse()
+                    .map_err(|_| format!("告警规则 {} 的持续时间不是有效的秒数", s))?,
+            ),
+            None => (s, 0),
+        };
+        let (metric, op, threshold) = AlertOp::ALL
+            .iter()
+            .find_map(|op| {
+                condition
+                    .split_once(op.as_str())
+                    .map(|(metric, threshold)| (metric, *op, threshold))
+            })
+            .ok_or_else(|| format!("告警规则 {} 缺少比较运算符（>、>=、<、<=）", s))?;
+        let threshold: f64 = threshold
+            .parse()
+            .ok()
+            .filter(|threshold: &f64| threshold.is_finite())
+            .ok_or_else(|| format!("告警规则 {} 的阈值不是有效的数字", s))?;
+        Ok(Self {
+            metric: metric.parse()?,
+            op,
+            threshold,
+            for_secs,
+        })
+    }
+}
+
+/// 告警状态
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum AlertStatus {
+    /// 触发告警
+    Firing,
+    /// 告警解除
+    Resolved,
+}
+
+/// 发送到 Webhook 的告警内容
+#[derive(Debug, Serialize)]
+struct AlertPayload {
+    status: AlertStatus,
+    /// 探针所属租户，默认租户不包含该字段
+    #[serde(skip_serializing_if = "String::is_empty")]
+    tenant_id: String,
+    server_id: u64,
+    metric: &'static str,
+    op: &'static str,
+    threshold: f64,
+    for_secs: u64,
+    /// 触发或解除时的指标值，因探针离线解除时为最后一次上报的值
+    value: f64,
+    /// 探针上报时间（秒），因探针离线解除时为服务器时间
+    timestamp: u64,
+    /// 非指标恢复导致告警解除时的原因，目前只有探针离线（`offline`）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+/// 主机信息中用于计算使用率的总量
+#[derive(Debug, Default, Clone, Copy)]
+struct HostTotals {
+    mem_total: u64,
+    disk_total: u64,
+}
+
+/// 单个探针在单条规则上的告警状态
+#[derive(Debug, Default)]
+struct RuleState {
+    /// 是否处于告警状态
+    firing: bool,
+    /// 条件开始与当前告警状态不一致的时间，None 表示一致
+    pending_since: Option<u64>,
+    /// 最近一次的指标值
+    value: f64,
+}
+
+/// 单个探针的告警状态，只保存处于告警或等待状态变化的规则
+#[derive(Debug, Default)]
+struct ServerAlerts {
+    /// 最近一次收到状态的服务器时间（秒），用于判断探针离线
+    last_seen: u64,
+    /// 以规则序号为键的告警状态
+    rules: HashMap<usize, RuleState>,
+}
+
+/// 告警引擎
+/// 在每次状态上报时按规则检查指标，告警触发和解除时向 Webhook 发送 JSON 通知。
+/// 告警解除后清除对应的状态；探针离线时解除其所有告警并清除状态，避免下线的探针一直处于告警状态
+#[derive(Debug)]
+pub struct AlertEngine {
+    webhook_url: String,
+    rules: Vec<AlertRule>,
+    client: reqwest::Client,
+    /// 各探针的告警状态，没有告警也没有等待状态变化的探针不保存
+    servers: Mutex<HashMap<ServerKey, ServerAlerts>>,
+    /// 各探针最近一次主机信息中的总内存和总磁盘空间
+    hosts: Mutex<HashMap<ServerKey, HostTotals>>,
+}
+
+impl AlertEngine {
+    pub fn new(webhook_url: String, rules: Vec<AlertRule>) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .map_err(|e| anyhow::anyhow!("创建 Webhook 客户端失败: {}", e))?;
+        Ok(Self {
+            webhook_url,
+            rules,
+            client,
+            servers: Mutex::new(HashMap::new()),
+            hosts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 记录探针主机的总内存和总磁盘空间，用于计算 `mem_used_percent` 和 `disk_used_percent`
+    pub fn update_host(&self, key: &ServerKey, host: &Host) {
+        self.hosts.lock().unwrap_or_else(|e| e.into_inner()).insert(
+            key.clone(),
+            HostTotals {
+                mem_total: host.mem_total,
+                disk_total: host.disk_total,
+            },
+        );
+    }
+
+    /// 检查探针的最新状态，告警触发或解除时在后台发送通知
+    /// 百分比指标在收到主机信息之前无法计算，相应的规则暂不检查
+    pub fn observe(&self, key: &ServerKey, timestamp: u64, state: &State) {
+        let totals = self
+            .hosts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .copied()
+            .unwrap_or_default();
+        let mut servers = self.servers.lock().unwrap_or_else(|e| e.into_inner());
+        let server = servers.entry(key.clone()).or_default();
+        server.last_seen = now_secs();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let Some(value) = rule.metric.value(state, totals) else {
+                continue;
+            };
+            let breached = rule.op.compare(value, rule.threshold);
+            let rule_state = server.rules.entry(index).or_default();
+            rule_state.value = value;
+            if breached == rule_state.firing {
+                rule_state.pending_since = None;
+                if !breached {
+                    server.rules.remove(&index);
+                }
+                continue;
+            }
+            let since = *rule_state.pending_since.get_or_insert(timestamp);
+            if timestamp.saturating_sub(since) < rule.for_secs {
+                continue;
+            }
+            if breached {
+                rule_state.firing = true;
+                rule_state.pending_since = None;
+                self.notify(key, rule, AlertStatus::Firing, value, timestamp, None);
+            } else {
+                server.rules.remove(&index);
+                self.notify(key, rule, AlertStatus::Resolved, value, timestamp, None);
+            }
+        }
+        if server.rules.is_empty() {
+            servers.remove(key);
+        }
+    }
+
+    /// 解除超过 `offline_after` 秒未上报状态的探针的所有告警，并清除其告警状态
+    /// 等待触发的规则直接丢弃，不发送通知
+    pub fn resolve_offline(&self, now: u64, offline_after: u64) {
+        let mut servers = self.servers.lock().unwrap_or_else(|e| e.into_inner());
+        servers.retain(|key, server| {
+            if now.saturating_sub(server.last_seen) <= offline_after {
+                return true;
+            }
+            for (index, rule_state) in &server.rules {
+                if rule_state.firing {
+                    self.notify(
+                        key,
+                        &self.rules[*index],
+                        AlertStatus::Resolved,
+                        rule_state.value,
+                        now,
+                        Some(OFFLINE_REASON),
+                    );
+                }
+            }
+            false
+        });
+    }
+
+    /// 记录日志并在后台发送告警通知
+    fn notify(
+        &self,
+        key: &ServerKey,
+        rule: &AlertRule,
+        status: AlertStatus,
+        value: f64,
+        timestamp: u64,
+        reason: Option<&'static str>,
+    ) {
+        let server_id = key.server_id;
+        let payload = AlertPayload {
+            status,
+            tenant_id: key.tenant_id.clone(),
+            server_id,
+            metric: rule.metric.as_str(),
+            op: rule.op.as_str(),
+            threshold: rule.threshold,
+            for_secs: rule.for_secs,
+            value,
+            timestamp,
+            reason,
+        };
+        tracing::info!(
+            server_id,
+            "探针 {} 告警{}{}: {} = {}，规则 {} {} {}",
+            server_id,
+            match status {
+                AlertStatus::Firing => "触发",
+                AlertStatus::Resolved => "解除",
+            },
+            if reason.is_some() {
+                "（探针离线）"
+            } else {
+                ""
+            },
+            payload.metric,
+            value,
+            payload.metric,
+            payload.op,
+            rule.threshold
+        );
+        self.send(payload);
+    }
+
+    /// 在后台发送告警通知，发送失败只记录日志，不影响状态上报
+    fn send(&self, payload: AlertPayload) {
+        let client = self.client.clone();
+        let url = self.webhook_url.clone();
+        tokio::spawn(async move {
+            let result = client
+                .post(&url)
+                .json(&payload)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                tracing::warn!(
+                    server_id = payload.server_id,
+                    "发送告警通知到 {} 失败: {}",
+                    url,
+                    e
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    // 常量定义
+    const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5); // 等待告警通知的最长时间
+    const OFFLINE_AFTER: u64 = 10; // 测试使用的离线判定时间（秒）
+
+    /// 启动接收告警通知的 Webhook 服务，返回地址和收到的通知内容
+    async fn spawn_webhook() -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 4096];
+                    // 读取完整的请求头和 Content-Length 指定长度的请求体
+                    while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                        request.extend_from_slice(&buf[..n]);
+                        let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") else {
+                            continue;
+                        };
+                        let length = String::from_utf8_lossy(&request[..end])
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse::<usize>().ok())?
+                            })
+                            .unwrap_or(0);
+                        let body = end + 4;
+                        if request.len() < body + length {
+                            continue;
+                        }
+                        let _ =
+                            tx.send(serde_json::from_slice(&request[body..body + length]).unwrap());
+                        let _ = stream
+                            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                            .await;
+                        return;
+                    }
+                });
+            }
+        });
+        (url, rx)
+    }
+
+    /// 等待下一条告警通知
+    async fn next(rx: &mut mpsc::UnboundedReceiver<serde_json::Value>) -> serde_json::Value {
+        tokio::time::timeout(RECEIVE_TIMEOUT, rx.recv())
+            .await
+            .expect("未收到告警通知")
+            .unwrap()
+    }
+
+    async fn engine(rules: &[&str]) -> (AlertEngine, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let (url, rx) = spawn_webhook().await;
+        let rules = rules.iter().map(|rule| rule.parse().unwrap()).collect();
+        (AlertEngine::new(url, rules).unwrap(), rx)
+    }
+
+    fn tracked(engine: &AlertEngine) -> usize {
+        engine.servers.lock().unwrap().len()
+    }
+
+    #[test]
+    fn percent_rules_are_parsed() {
+        let rule: AlertRule = "mem_used_percent>90:60".parse().unwrap();
+        assert_eq!(rule.metric, AlertMetric::MemUsedPercent);
+        let rule: AlertRule = "disk_used_percent>=95".parse().unwrap();
+        assert_eq!(rule.metric, AlertMetric::DiskUsedPercent);
+    }
+
+    #[tokio::test]
+    async fn percent_metric_waits_for_host_totals() {
+        let (engine, mut rx) = engine(&["mem_used_percent>80"]).await;
+        let key = ServerKey::new("", 1);
+        let state = State {
+            mem_used: 900,
+            ..Default::default()
+        };
+
+        // 没有主机信息时无法计算使用率
+        engine.observe(&key, 1, &state);
+        assert_eq!(tracked(&engine), 0);
+
+        let host = Host {
+            mem_total: 1000,
+            ..Default::default()
+        };
+        engine.update_host(&key, &host);
+        engine.observe(&key, 2, &state);
+        let payload = next(&mut rx).await;
+        assert_eq!(payload["status"], "firing");
+        assert_eq!(payload["metric"], "mem_used_percent");
+        assert_eq!(payload["value"], 90.0);
+    }
+
+    #[tokio::test]
+    async fn recovery_resolves_and_clears_state() {
+        let (engine, mut rx) = engine(&["disk_used_percent>=90"]).await;
+        let key = ServerKey::new("tenant", 1);
+        let host = Host {
+            disk_total: 100,
+            ..Default::default()
+        };
+        engine.update_host(&key, &host);
+
+        let full = State {
+            disk_used: 95,
+            ..Default::default()
+        };
+        engine.observe(&key, 1, &full);
+        assert_eq!(next(&mut rx).await["status"], "firing");
+        assert_eq!(tracked(&engine), 1);
+
+        let recovered = State {
+            disk_used: 50,
+            ..Default::default()
+        };
+        engine.observe(&key, 2, &recovered);
+        let payload = next(&mut rx).await;
+        assert_eq!(payload["status"], "resolved");
+        assert_eq!(payload["tenant_id"], "tenant");
+        assert_eq!(payload["value"], 50.0);
+        assert!(payload.get("reason").is_none());
+        assert_eq!(tracked(&engine), 0);
+    }
+
+    #[tokio::test]
+    async fn offline_server_alerts_are_resolved() {
+        let (engine, mut rx) = engine(&["cpu>90", "load1>4:60"]).await;
+        let key = ServerKey::new("", 1);
+        let busy = State {
+            cpu_usage: 95.0,
+            load1: 8.0,
+            ..Default::default()
+        };
+        engine.observe(&key, 1, &busy);
+        assert_eq!(next(&mut rx).await["status"], "firing");
+
+        engine.resolve_offline(now_secs(), OFFLINE_AFTER);
+        assert_eq!(tracked(&engine), 1);
+
+        // 只有已触发的告警发送解除通知，等待触发的规则直接丢弃
+        engine.resolve_offline(now_secs() + OFFLINE_AFTER + 1, OFFLINE_AFTER);
+        let payload = next(&mut rx).await;
+        assert_eq!(payload["status"], "resolved");
+        assert_eq!(payload["metric"], "cpu");
+        assert_eq!(payload["reason"], "offline");
+        assert_eq!(payload["value"], 95.0);
+        assert_eq!(tracked(&engine), 0);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use salvo::prelude::*;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::auth::TenantAuth;
+use crate::rpc_service::{ServerKey, SharedState};
+use crate::storage::Storage;
+
+// 常量定义
+const DEFAULT_RANGE_SECONDS: u64 = 3600; // 未指定起始时间时默认查询最近一小时
+const DEFAULT_LIMIT: u32 = 1000; // 默认最多返回的记录数
+const MAX_LIMIT: u32 = 10000; // 单次查询最多返回的记录数
+
+/// 历史状态查询参数
+#[derive(Debug, Deserialize)]
+struct StateQuery {
+    /// 起始时间（秒）
+    from: Option<u64>,
+    /// 结束时间（秒）
+    to: Option<u64>,
+    /// 最多返回的记录数
+    limit: Option<u32>,
+    /// 降采样间隔（秒）
+    step: Option<u64>,
+}
+
+/// 历史状态查询接口
+/// `GET /api/servers/<id>/states?from=&to=&limit=&step=`
+#[derive(Debug)]
+pub struct StateHistoryHandler {
+    storage: Storage,
+    auth: TenantAuth,
+}
+
+impl StateHistoryHandler {
+    pub fn new(storage: Storage, auth: TenantAuth) -> Self {
+        Self { storage, auth }
+    }
+}
+
+#[async_trait]
+impl Handler for StateHistoryHandler {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        let tenant_id = match self.auth.tenant(req) {
+            Ok(tenant_id) => tenant_id,
+            Err(e) => {
+                res.render(e);
+                return;
+            }
+        };
+        let Some(server_id) = req.param::<u64>("id") else {
+            res.render(StatusError::bad_request().brief("探针ID格式错误"));
+            return;
+        };
+        let query = match req.parse_queries::<StateQuery>() {
+            Ok(query) => query,
+            Err(e) => {
+                res.render(StatusError::bad_request().brief(format!("查询参数错误: {}", e)));
+                return;
+            }
+        };
+
+        let to = query.to.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs())
+        });
+        let from = query
+            .from
+            .unwrap_or_else(|| to.saturating_sub(DEFAULT_RANGE_SECONDS));
+        if from > to {
+            res.render(StatusError::bad_request().brief("起始时间不能晚于结束时间"));
+            return;
+        }
+        let step = query.step.unwrap_or(1).max(1);
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        match self.storage.has_server(&tenant_id, server_id).await {
+            Ok(true) => {}
+            Ok(false) => {
+                res.render(StatusError::not_found().brief("探针不存在"));
+                return;
+            }
+            Err(e) => {
+                tracing::error!("查询探针 {} 失败: {}", server_id, e);
+                res.render(StatusError::internal_server_error());
+                return;
+            }
+        }
+
+        match self
+            .storage
+            .query_states(&tenant_id, server_id, from, to, step, limit)
+            .await
+        {
+            Ok(states) => res.render(Json(states)),
+            Err(e) => {
+                tracing::error!("查询探针 {} 历史状态失败: {}", server_id, e);
+                res.render(StatusError::internal_server_error());
+            }
+        }
+    }
+}
+
+/// 内存中最近状态查询接口
+/// `GET /api/servers/<id>/history`，按上报顺序返回最近的状态，条数由 `PANDA_STATE_HISTORY_LEN` 决定，
+/// 服务重启后清空；需要更长时间范围时使用 `/api/servers/<id>/states`
+#[derive(Debug)]
+pub struct RecentStatesHandler {
+    shared_states: Arc<Mutex<SharedState>>,
+    auth: TenantAuth,
+}
+
+impl RecentStatesHandler {
+    pub fn new(shared_states: Arc<Mutex<SharedState>>, auth: TenantAuth) -> Self {
+        Self {
+            shared_states,
+            auth,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for RecentStatesHandler {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        let tenant_id = match self.auth.tenant(req) {
+            Ok(tenant_id) => tenant_id,
+            Err(e) => {
+                res.render(e);
+                return;
+            }
+        };
+        let Some(server_id) = req.param::<u64>("id") else {
+            res.render(StatusError::bad_request().brief("探针ID格式错误"));
+            return;
+        };
+        let key = ServerKey::new(&tenant_id, server_id);
+        match self.shared_states.lock().await.history(&key) {
+            Some(history) => res.render(Json(history)),
+            None => res.render(StatusError::not_found().brief("探针不存在")),
+        }
+    }
+}
+
+/// 探针列表接口
+/// `GET /api/servers`，返回所属租户已上报过状态的探针及其在线状态和标签，按探针 ID 排序
+#[derive(Debug)]
+pub struct ServerListHandler {
+    shared_states: Arc<Mutex<SharedState>>,
+    auth: TenantAuth,
+}
+
+impl ServerListHandler {
+    pub fn new(shared_states: Arc<Mutex<SharedState>>, auth: TenantAuth) -> Self {
+        Self {
+            shared_states,
+            auth,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for ServerListHandler {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        let tenant_id = match self.auth.tenant(req) {
+            Ok(tenant_id) => tenant_id,
+            Err(e) => {
+                res.render(e);
+                return;
+            }
+        };
+        let servers = self.shared_states.lock().await.list_servers(&tenant_id);
+        res.render(Json(servers));
+    }
+}
+
+/// 健康检查接口
+/// `GET /health`，后台状态广播任务退出时返回 503
+#[derive(Debug)]
+pub struct HealthHandler {
+    shared_states: Arc<Mutex<SharedState>>,
+    state_check_task: Arc<JoinHandle<()>>,
+}
+
+impl HealthHandler {
+    pub fn new(
+        shared_states: Arc<Mutex<SharedState>>,
+        state_check_task: Arc<JoinHandle<()>>,
+    ) -> Self {
+        Self {
+            shared_states,
+            state_check_task,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for HealthHandler {
+    async fn handle(
+        &self,
+        _req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        if self.state_check_task.is_finished() {
+            res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+            res.render(Json(serde_json::json!({
+                "status": "unavailable",
+                "reason": "state broadcast task stopped",
+            })));
+            return;
+        }
+
+        let connected_servers = self.shared_states.lock().await.online_count();
+        res.render(Json(serde_json::json!({
+            "status": "ok",
+            "connected_servers": connected_servers,
+        })));
+    }
+}
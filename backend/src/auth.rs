@@ -0,0 +1,61 @@
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use salvo::prelude::*;
+use serde::Deserialize;
+
+// 常量定义
+const BEARER_PREFIX: &str = "Bearer "; // Authorization 请求头中令牌的前缀
+const TOKEN_QUERY: &str = "token"; // 传递令牌的查询参数，浏览器的 WebSocket 无法设置请求头
+
+/// 令牌中使用的声明
+#[derive(Debug, Deserialize)]
+struct Claims {
+    /// 所属租户，缺省时为默认租户
+    #[serde(default)]
+    tenant_id: String,
+}
+
+/// 仪表盘请求的租户认证
+/// 配置了 JWT 密钥时，请求必须携带 HS256 签名且包含 `exp` 的令牌，所属租户取自令牌的 `tenant_id` 声明；
+/// 未配置时不校验令牌，所有请求属于默认租户
+#[derive(Clone)]
+pub struct TenantAuth {
+    key: Option<DecodingKey>,
+    validation: Validation,
+}
+
+impl std::fmt::Debug for TenantAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TenantAuth")
+            .field("enabled", &self.key.is_some())
+            .finish()
+    }
+}
+
+impl TenantAuth {
+    pub fn new(secret: Option<&str>) -> Self {
+        Self {
+            key: secret.map(|secret| DecodingKey::from_secret(secret.as_bytes())),
+            validation: Validation::new(Algorithm::HS256),
+        }
+    }
+
+    /// 校验请求携带的令牌，返回请求所属的租户
+    /// 令牌优先从 `Authorization: Bearer <令牌>` 请求头读取，其次从 `token` 查询参数读取
+    pub fn tenant(&self, req: &Request) -> Result<String, StatusError> {
+        let Some(key) = &self.key else {
+            return Ok(String::new());
+        };
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+            .or_else(|| req.query::<&str>(TOKEN_QUERY))
+            .ok_or_else(|| StatusError::unauthorized().brief("缺少授权令牌"))?;
+        let data = jsonwebtoken::decode::<Claims>(token, key, &self.validation).map_err(|e| {
+            tracing::warn!("令牌验证失败: {}", e);
+            StatusError::unauthorized().brief("授权令牌无效")
+        })?;
+        Ok(data.claims.tenant_id)
+    }
+}
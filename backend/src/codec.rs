@@ -0,0 +1,157 @@
+use std::str::FromStr;
+
+use common::panda_monitor::{Command, CommandType, State};
+
+/// 广播给仪表盘的状态更新的序列化格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Serialization {
+    /// 状态数组的 JSON，写入命令的 `data` 字段
+    #[default]
+    Json,
+    /// 状态数组的 MessagePack（字段以名称为键，结构与 JSON 一致），写入命令的 `payload` 字段
+    /// 编码更快、体积更小
+    Msgpack,
+}
+
+impl Serialization {
+    /// 构建广播状态更新的命令
+    pub fn state_update(self, states: &[State], server_ids: Vec<u64>) -> anyhow::Result<Command> {
+        let (data, payload) = match self {
+            Self::Json => (serde_json::to_string(states)?, Vec::new()),
+            Self::Msgpack => (String::new(), rmp_serde::to_vec_named(states)?),
+        };
+        Ok(Command {
+            command: CommandType::StateUpdate.into(),
+            data,
+            server_ids,
+            payload,
+            ..Default::default()
+        })
+    }
+
+    /// 反序列化状态更新命令中的状态数组
+    pub fn decode(self, command: &Command) -> anyhow::Result<Vec<State>> {
+        match self {
+            Self::Json => Ok(serde_json::from_str(&command.data)?),
+            Self::Msgpack => Ok(rmp_serde::from_slice(&command.payload)?),
+        }
+    }
+}
+
+impl FromStr for Serialization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "msgpack" => Ok(Self::Msgpack),
+            _ => Err("可选值为 json 或 msgpack".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use common::panda_monitor::{Check, ProcessStat};
+
+    use super::*;
+
+    // 常量定义
+    const SERVER_COUNT: u64 = 60; // 一次广播中的探针数量
+    const ITERATIONS: u32 = 2000; // 计时的序列化次数
+
+    /// 构造一批字段基本填满的状态，模拟一次广播
+    fn states() -> Vec<State> {
+        (0..SERVER_COUNT)
+            .map(|i| State {
+                cpu_usage: 12.5 + i as f64,
+                mem_used: 3_221_225_472 + i * 1_048_576,
+                mem_available: 4_294_967_296,
+                mem_used_real: 2_147_483_648,
+                swap_used: 104_857_600,
+                disk_used: 53_687_091_200 + i,
+                net_in_transfer: 987_654_321_000 + i,
+                net_out_transfer: 123_456_789_000 + i,
+                net_in_speed: 1_250_000,
+                net_out_speed: 250_000,
+                disk_read_speed: 4_096_000,
+                disk_write_speed: 8_192_000,
+                load1: 0.52,
+                load5: 0.61,
+                load15: 0.74,
+                load1_per_core: 0.13,
+                load5_per_core: 0.15,
+                load15_per_core: 0.18,
+                open_fds: 2_048,
+                tcp_connections: 120,
+                tcp_established: 80,
+                tcp_listen: 12,
+                custom_metrics: HashMap::from([
+                    ("queue.depth".to_string(), 12.0),
+                    ("db.connections".to_string(), 35.0),
+                ]),
+                checks: vec![Check {
+                    name: "nginx".to_string(),
+                    success: true,
+                    ..Default::default()
+                }],
+                watched_processes: vec![ProcessStat {
+                    name: "postgres".to_string(),
+                    running: true,
+                    count: 8,
+                    cpu_usage: 3.5,
+                    mem_used: 536_870_912,
+                }],
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// 序列化 `ITERATIONS` 次，返回平均耗时和消息大小
+    fn measure(serialization: Serialization, states: &[State]) -> (Duration, usize) {
+        let server_ids: Vec<u64> = (0..SERVER_COUNT).collect();
+        let start = Instant::now();
+        let mut size = 0;
+        for _ in 0..ITERATIONS {
+            let command = serialization
+                .state_update(states, server_ids.clone())
+                .unwrap();
+            size = command.data.len() + command.payload.len();
+        }
+        (start.elapsed() / ITERATIONS, size)
+    }
+
+    #[test]
+    fn state_update_round_trip() {
+        let states = states();
+        for serialization in [Serialization::Json, Serialization::Msgpack] {
+            let command = serialization.state_update(&states, vec![1, 2]).unwrap();
+            assert_eq!(command.command(), CommandType::StateUpdate);
+            assert_eq!(command.server_ids, vec![1, 2]);
+            assert_eq!(serialization.decode(&command).unwrap(), states);
+        }
+    }
+
+    /// 计时比较两种格式，需使用 release 构建运行：
+    /// `cargo test -p backend --release codec -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn msgpack_is_faster_for_large_batches() {
+        let states = states();
+        // 预热
+        measure(Serialization::Json, &states);
+        measure(Serialization::Msgpack, &states);
+
+        let (json_time, json_size) = measure(Serialization::Json, &states);
+        let (msgpack_time, msgpack_size) = measure(Serialization::Msgpack, &states);
+        println!(
+            "{} 个探针: json {:?} {} 字节，msgpack {:?} {} 字节",
+            SERVER_COUNT, json_time, json_size, msgpack_time, msgpack_size
+        );
+        assert!(msgpack_time < json_time);
+        assert!(msgpack_size < json_size);
+    }
+}
@@ -0,0 +1,292 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use clap::Parser;
+use common::version::Version;
+
+use crate::alert::AlertRule;
+use crate::codec::Serialization;
+
+// 常量定义
+const DEFAULT_RPC_ADDR: &str = "0.0.0.0:50051"; // 默认 RPC 监听地址
+const DEFAULT_HTTP_ADDR: &str = "0.0.0.0:8000"; // 默认 HTTP 监听地址
+const DEFAULT_MIN_AGENT_VERSION: &str = "0.1.0"; // 默认最低兼容探针版本
+const DEFAULT_SUPPORTED_AGENT_VERSION: &str = env!("CARGO_PKG_VERSION"); // 默认建议的最低探针版本，与服务端版本一致
+const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 10; // 默认命令流心跳间隔（秒）
+const DEFAULT_STATE_RATE_LIMIT: f64 = 5.0; // 默认每个探针每秒允许的状态上报次数
+const DEFAULT_BROADCAST_CAPACITY: usize = 128; // 默认命令广播通道容量
+const DEFAULT_COMMAND_BUFFER: usize = 128; // 默认每个命令流的发送缓冲大小
+const DEFAULT_MAX_CLOCK_SKEW_SECS: u64 = 300; // 默认允许探针上报时间超前服务器的秒数
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024; // 默认单条 RPC 消息的最大字节数
+const DEFAULT_STATE_HISTORY_LEN: usize = 300; // 默认每个探针在内存中保留的最近状态条数
+const DEFAULT_STORAGE_QUEUE: usize = 4096; // 默认等待写入数据库的状态条数上限
+const DEFAULT_RETENTION_RAW_SECS: u64 = 7 * 24 * 3600; // 默认原始状态保留时间（秒）
+const DEFAULT_RETENTION_ROLLUP_SECS: u64 = 90 * 24 * 3600; // 默认分钟聚合保留时间（秒）
+
+/// 日志输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// 便于阅读的文本格式
+    Text,
+    /// 每行一个 JSON 对象，便于日志系统解析字段
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("可选值为 text 或 json".to_string()),
+        }
+    }
+}
+
+/// Panda Monitor 后端服务
+// 每个配置项都可以通过命令行参数或对应的环境变量指定，命令行参数优先
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    /// RPC 服务监听地址
+    #[arg(
+        long = "grpc-addr",
+        env = "PANDA_RPC_ADDR",
+        value_name = "ADDR",
+        default_value = DEFAULT_RPC_ADDR
+    )]
+    pub rpc_addr: SocketAddr,
+    /// HTTP 服务监听地址
+    #[arg(
+        long,
+        env = "PANDA_HTTP_ADDR",
+        value_name = "ADDR",
+        default_value = DEFAULT_HTTP_ADDR
+    )]
+    pub http_addr: SocketAddr,
+    /// RPC 服务额外监听的 Unix 域套接字路径，供同一主机上的探针通过 `unix://` 地址连接，不使用 TLS
+    /// 启动时会删除该路径上残留的套接字文件，仅支持 Unix 平台
+    #[arg(long = "grpc-uds", env = "PANDA_RPC_UDS", value_name = "PATH")]
+    pub rpc_uds_path: Option<String>,
+    /// RPC 服务 TLS 证书路径（PEM）
+    #[arg(long = "tls-cert", env = "PANDA_TLS_CERT", value_name = "PATH")]
+    pub tls_cert_path: Option<String>,
+    /// RPC 服务 TLS 私钥路径（PEM）
+    #[arg(long = "tls-key", env = "PANDA_TLS_KEY", value_name = "PATH")]
+    pub tls_key_path: Option<String>,
+    /// 校验客户端证书的 CA 证书路径（PEM），配置后要求探针提供由该 CA 签发的证书（mTLS）
+    /// 未提供有效客户端证书的连接在 TLS 握手阶段即被拒绝。探针需要同时配置 `--ca-cert`、`--client-cert` 和 `--client-key`。
+    /// 探针的租户由客户端证书主题中的组织（O）确定，与探针声明的租户不一致时拒绝；未配置时只接受默认租户的探针
+    #[arg(
+        long = "tls-client-ca",
+        env = "PANDA_TLS_CLIENT_CA",
+        value_name = "PATH"
+    )]
+    pub tls_client_ca_path: Option<String>,
+    /// 最低兼容的探针版本，低于该版本的探针会被拒绝
+    #[arg(long, env = "PANDA_MIN_AGENT_VERSION", default_value = DEFAULT_MIN_AGENT_VERSION)]
+    pub min_agent_version: Version,
+    /// 建议的最低探针版本，低于该版本的探针会提示升级但仍可上报
+    #[arg(
+        long,
+        env = "PANDA_SUPPORTED_AGENT_VERSION",
+        default_value = DEFAULT_SUPPORTED_AGENT_VERSION
+    )]
+    pub supported_agent_version: Version,
+    /// 是否允许多个主机使用相同的探针 ID
+    #[arg(long, env = "PANDA_ALLOW_DUPLICATE_IDS")]
+    pub allow_duplicate_ids: bool,
+    /// 命令流心跳间隔（秒）
+    #[arg(
+        long = "heartbeat-interval",
+        env = "PANDA_HEARTBEAT_INTERVAL",
+        default_value_t = DEFAULT_HEARTBEAT_INTERVAL_SECS
+    )]
+    pub heartbeat_interval_secs: u64,
+    /// 每个探针每秒允许的状态上报次数，超出的状态被丢弃，0 表示不限制
+    /// 探针使用小于 1 秒的上报间隔时需相应调高，例如 100ms 间隔至少需要 10
+    #[arg(
+        long,
+        visible_alias = "max-reports-per-sec",
+        env = "PANDA_STATE_RATE_LIMIT",
+        default_value_t = DEFAULT_STATE_RATE_LIMIT
+    )]
+    pub state_rate_limit: f64,
+    /// 命令广播通道容量
+    /// 仪表盘或探针积压的消息超过该数量时，最旧的消息会被丢弃。
+    /// 通道在启动时按容量一次性分配，容量越大，慢速订阅者能积压的命令越多，常驻内存也越高。
+    #[arg(
+        long,
+        env = "PANDA_BROADCAST_CAPACITY",
+        default_value_t = DEFAULT_BROADCAST_CAPACITY
+    )]
+    pub broadcast_capacity: usize,
+    /// 每个探针命令流的发送缓冲大小
+    /// 探针接收命令慢于服务端下发时最多积压的命令数，缓冲满后暂停转发，直到探针读取。
+    /// 每个连接的探针单独占用一份缓冲，最坏情况下的内存占用约为 探针数 × 缓冲大小 × 单条命令大小。
+    #[arg(
+        long,
+        env = "PANDA_COMMAND_BUFFER",
+        default_value_t = DEFAULT_COMMAND_BUFFER
+    )]
+    pub command_buffer: usize,
+    /// 允许探针上报时间超前服务器时间的最大秒数，超出的请求会被拒绝
+    #[arg(
+        long = "max-clock-skew",
+        env = "PANDA_MAX_CLOCK_SKEW",
+        default_value_t = DEFAULT_MAX_CLOCK_SKEW_SECS
+    )]
+    pub max_clock_skew_secs: u64,
+    /// 单条 RPC 请求消息的最大字节数，超出的请求返回 RESOURCE_EXHAUSTED
+    #[arg(
+        long,
+        env = "PANDA_MAX_MESSAGE_SIZE",
+        value_name = "BYTES",
+        default_value_t = DEFAULT_MAX_MESSAGE_SIZE
+    )]
+    pub max_message_size: usize,
+    /// 每个探针在内存中保留的最近状态条数，供 `/api/servers/<id>/history` 查询，0 表示不保留
+    #[arg(
+        long,
+        env = "PANDA_STATE_HISTORY_LEN",
+        default_value_t = DEFAULT_STATE_HISTORY_LEN
+    )]
+    pub state_history_len: usize,
+    /// 等待写入数据库的状态条数上限
+    /// 状态由后台任务批量写入数据库，数据库写入慢于上报时最多积压该数量的状态，
+    /// 队列满后新的状态不再保存（实时状态不受影响），并向探针返回保存失败。
+    #[arg(
+        long,
+        env = "PANDA_STORAGE_QUEUE",
+        default_value_t = DEFAULT_STORAGE_QUEUE
+    )]
+    pub storage_queue: usize,
+    /// 数据库中原始状态的保留时间（秒），0 表示永久保留且不聚合
+    /// 超过保留时间的原始状态按分钟聚合为 CPU、内存和网络速度的最小值、最大值和平均值后删除，
+    /// 历史状态查询对已聚合的时间段返回聚合记录。
+    #[arg(
+        long = "retention-raw",
+        env = "PANDA_RETENTION_RAW",
+        value_name = "SECS",
+        default_value_t = DEFAULT_RETENTION_RAW_SECS
+    )]
+    pub retention_raw_secs: u64,
+    /// 分钟聚合的保留时间（秒），超过后合并为小时聚合，小时聚合永久保留；0 表示永久保留分钟聚合
+    /// 必须大于 `--retention-raw`。
+    #[arg(
+        long = "retention-rollup",
+        env = "PANDA_RETENTION_ROLLUP",
+        value_name = "SECS",
+        default_value_t = DEFAULT_RETENTION_ROLLUP_SECS
+    )]
+    pub retention_rollup_secs: u64,
+    /// 告警通知的 Webhook 地址，告警触发和解除时以 POST 方式发送 JSON
+    #[arg(long, env = "PANDA_WEBHOOK_URL", value_name = "URL")]
+    pub webhook_url: Option<String>,
+    /// 告警规则，格式为 `<指标><运算符><阈值>[:<持续秒数>]`，可用逗号分隔多条，例如 `cpu>90:60,load1_per_core>=2:300`
+    /// 可用指标: cpu（%）、mem_used、mem_available、mem_used_real、swap_used、disk_used（字节）、
+    /// mem_used_percent、disk_used_percent（%，探针上报主机信息后才开始检查）、load1、load1_per_core。
+    /// 探针超过 10 秒未上报状态时视为离线，其告警以 `reason: "offline"` 解除。
+    #[arg(
+        long = "alert-rule",
+        env = "PANDA_ALERT_RULES",
+        value_name = "RULE",
+        value_delimiter = ','
+    )]
+    pub alert_rules: Vec<AlertRule>,
+    /// 允许跨域访问 HTTP 和 WebSocket 接口的来源，可用逗号分隔，`*` 表示允许任意来源
+    /// 未配置时不添加 CORS 响应头，只允许同源访问
+    #[arg(
+        long,
+        env = "PANDA_CORS_ORIGINS",
+        value_name = "ORIGINS",
+        value_delimiter = ','
+    )]
+    pub cors_origins: Vec<String>,
+    /// 是否在 RPC 端口上开启 gRPC 服务反射，便于使用 grpcurl 调试，生产环境建议关闭
+    #[arg(long, env = "PANDA_GRPC_REFLECTION")]
+    pub grpc_reflection: bool,
+    /// 日志输出格式（text 或 json），日志级别由 `RUST_LOG` 控制
+    #[arg(long, env = "PANDA_LOG_FORMAT", default_value = "text")]
+    pub log_format: LogFormat,
+    /// 广播状态更新的序列化格式（json 或 msgpack）
+    /// 探针较多时 msgpack 的编码耗时和消息体积都小于 json。WebSocket 连接未指定 `encoding` 参数时
+    /// 按该格式推送，快照消息的 `encoding` 字段告知客户端实际使用的编码。
+    #[arg(long, env = "PANDA_SERIALIZE", default_value = "json")]
+    pub serialize: Serialization,
+    /// 仪表盘令牌的 JWT 密钥（HS256）
+    /// 配置后 WebSocket 和 HTTP 接口必须携带包含 `exp` 的令牌（`Authorization: Bearer <令牌>` 或 `token` 查询参数），
+    /// 只能看到和操作令牌中 `tenant_id` 声明所属租户的探针，缺少该声明时为默认租户。
+    /// 未配置时不校验令牌，只能看到默认租户的探针。
+    #[arg(long, env = "PANDA_JWT_SECRET", hide_env_values = true)]
+    pub jwt_secret: Option<String>,
+}
+
+impl Config {
+    /// 从命令行参数和环境变量读取配置，未配置的项使用默认值
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = Self::parse();
+        // 环境变量为空时视为未配置
+        for path in [
+            &mut config.tls_cert_path,
+            &mut config.tls_key_path,
+            &mut config.tls_client_ca_path,
+            &mut config.webhook_url,
+            &mut config.rpc_uds_path,
+            &mut config.jwt_secret,
+        ] {
+            if path.as_deref() == Some("") {
+                *path = None;
+            }
+        }
+        config
+            .cors_origins
+            .retain(|origin| !origin.trim().is_empty());
+        if config.tls_cert_path.is_some() != config.tls_key_path.is_some() {
+            return Err(anyhow::anyhow!("TLS 证书和私钥必须同时配置"));
+        }
+        if config.tls_client_ca_path.is_some() && config.tls_cert_path.is_none() {
+            return Err(anyhow::anyhow!(
+                "配置客户端 CA 证书时必须同时配置 TLS 证书和私钥"
+            ));
+        }
+        if cfg!(not(unix)) && config.rpc_uds_path.is_some() {
+            return Err(anyhow::anyhow!("当前平台不支持 Unix 域套接字"));
+        }
+        if !config.alert_rules.is_empty() && config.webhook_url.is_none() {
+            return Err(anyhow::anyhow!("配置告警规则时必须同时配置 Webhook 地址"));
+        }
+        if let Some(url) = &config.webhook_url {
+            reqwest::Url::parse(url)
+                .map_err(|e| anyhow::anyhow!("无效的 Webhook 地址 {}: {}", url, e))?;
+        }
+        if config.heartbeat_interval_secs == 0 {
+            return Err(anyhow::anyhow!("心跳间隔不能为0"));
+        }
+        if config.max_message_size == 0 {
+            return Err(anyhow::anyhow!("RPC 消息大小上限不能为0"));
+        }
+        if config.broadcast_capacity == 0 {
+            return Err(anyhow::anyhow!("广播通道容量不能为0"));
+        }
+        if config.storage_queue == 0 {
+            return Err(anyhow::anyhow!("状态写入队列容量不能为0"));
+        }
+        if config.command_buffer == 0 {
+            return Err(anyhow::anyhow!("命令流缓冲大小不能为0"));
+        }
+        if config.retention_raw_secs != 0
+            && config.retention_rollup_secs != 0
+            && config.retention_rollup_secs <= config.retention_raw_secs
+        {
+            return Err(anyhow::anyhow!(
+                "分钟聚合的保留时间必须大于原始状态的保留时间"
+            ));
+        }
+        if !(config.state_rate_limit >= 0.0 && config.state_rate_limit.is_finite()) {
+            return Err(anyhow::anyhow!("状态上报限流速率必须为非负数"));
+        }
+        Ok(config)
+    }
+}
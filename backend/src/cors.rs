@@ -0,0 +1,78 @@
+use salvo::http::header::{self, HeaderValue};
+use salvo::http::Method;
+use salvo::prelude::*;
+
+// 常量定义
+const ALLOWED_METHODS: &str = "GET, POST, OPTIONS"; // 允许的跨域请求方法
+const DEFAULT_ALLOWED_HEADERS: &str = "Authorization, Content-Type"; // 预检请求未声明请求头时允许的请求头
+const PREFLIGHT_MAX_AGE_SECS: &str = "86400"; // 预检结果的缓存时间（秒）
+
+/// 跨域请求处理
+/// 只为允许的来源添加 CORS 响应头，并直接响应预检请求；
+/// 不在允许列表中的来源不添加响应头，由浏览器拦截
+#[derive(Debug)]
+pub struct CorsHandler {
+    /// 允许的来源，`*` 表示允许任意来源
+    allowed_origins: Vec<String>,
+}
+
+impl CorsHandler {
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        Self { allowed_origins }
+    }
+
+    /// 判断来源是否被允许
+    fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+}
+
+#[async_trait]
+impl Handler for CorsHandler {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let Some(origin) = req.headers().get(header::ORIGIN).cloned() else {
+            return;
+        };
+        if !origin.to_str().is_ok_and(|origin| self.allows(origin)) {
+            return;
+        }
+
+        let headers = res.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+
+        // 预检请求直接返回，不进入路由
+        let is_preflight = req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if !is_preflight {
+            return;
+        }
+        let allowed_headers = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .cloned()
+            .unwrap_or(HeaderValue::from_static(DEFAULT_ALLOWED_HEADERS));
+        let headers = res.headers_mut();
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static(ALLOWED_METHODS),
+        );
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+        headers.insert(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from_static(PREFLIGHT_MAX_AGE_SECS),
+        );
+        res.status_code(StatusCode::NO_CONTENT);
+        ctrl.skip_rest();
+    }
+}
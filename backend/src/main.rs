@@ -1,40 +1,319 @@
+mod alert;
+mod api_handler;
+mod auth;
+mod codec;
+mod config;
+mod cors;
+mod rate_limiter;
 mod rpc_service;
+mod storage;
 mod ws_handler;
 
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use api_handler::{HealthHandler, RecentStatesHandler, ServerListHandler, StateHistoryHandler};
+use auth::TenantAuth;
 use common::panda_monitor::panda_monitor_server::PandaMonitorServer;
-use common::panda_monitor::Command;
-use rpc_service::PandaMonitorService;
+use common::panda_monitor::{Command, CommandType};
+use config::{Config, LogFormat};
+use cors::CorsHandler;
+use rpc_service::{receive_error_layer, PandaMonitorService, SharedState};
 use salvo::prelude::*;
-use tokio::sync::broadcast;
-use tonic::transport::Server as TonicServer;
+use storage::Storage;
+use tokio::sync::{broadcast, watch, Mutex};
+use tokio::time::Instant;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Certificate, Identity, Server as TonicServer, ServerTlsConfig};
+use tracing_subscriber::EnvFilter;
 use ws_handler::WsHandler;
 
+// 常量定义
+const GRACEFUL_SHUTDOWN_TIMEOUT_SECS: u64 = 10; // 优雅关闭时等待连接排空的最长时间
+const DEFAULT_LOG_LEVEL: &str = "info"; // 未设置 RUST_LOG 时的日志级别
+const ROLLUP_INTERVAL_SECS: u64 = 300; // 聚合历史状态的间隔
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    // 读取配置
+    let config = Config::load()?;
+
     // 初始化日志
-    tracing_subscriber::fmt::init();
+    init_logging(config.log_format);
+
+    // 连接数据库
+    let storage = Storage::connect().await?;
+    if config.retention_raw_secs != 0 {
+        spawn_rollup_task(
+            storage.clone(),
+            config.retention_raw_secs,
+            config.retention_rollup_secs,
+        );
+    }
+    // 状态由后台任务写入数据库，RPC 服务只负责放入队列
+    let (state_writer, state_writer_task) = storage.spawn_writer(config.storage_queue);
 
     // 创建命令通道
-    let (command_tx, _) = broadcast::channel::<Command>(128);
+    let (command_tx, _) = broadcast::channel::<Command>(config.broadcast_capacity);
+
+    // 创建共享状态，RPC 服务负责写入，WebSocket 与健康检查只读取
+    let shared_states = Arc::new(Mutex::new(SharedState::new(config.state_history_len)));
+
+    // 先绑定两个端口，地址无效或端口被占用时立即退出
+    let rpc_listener = tokio::net::TcpListener::bind(config.rpc_addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("RPC 服务无法监听 {}: {}", config.rpc_addr, e))?;
+    let acceptor = TcpListener::new(config.http_addr)
+        .try_bind()
+        .await
+        .map_err(|e| anyhow::anyhow!("HTTP 服务无法监听 {}: {}", config.http_addr, e))?;
 
     // 初始化 RPC 服务器
-    tracing::info!("Starting RPC server...");
-    let rpc_addr = "0.0.0.0:50051".parse()?;
-    let rpc_service = PandaMonitorServer::new(PandaMonitorService::new(command_tx.clone()));
-    let rpc_server = TonicServer::builder()
+    tracing::info!("Starting RPC server on {}...", config.rpc_addr);
+    let monitor_service = PandaMonitorService::new(
+        command_tx.clone(),
+        state_writer,
+        shared_states.clone(),
+        &config,
+    )?;
+    let state_check_task = monitor_service.state_check_task();
+    let rpc_service =
+        PandaMonitorServer::new(monitor_service).max_decoding_message_size(config.max_message_size);
+    let (rpc_shutdown_tx, rpc_shutdown_rx) = watch::channel(());
+    let mut rpc_builder = TonicServer::builder().layer(receive_error_layer());
+    if let Some(tls_config) = load_tls_config(&config)? {
+        tracing::info!("RPC server TLS enabled");
+        if config.tls_client_ca_path.is_some() {
+            tracing::info!("RPC server client certificate authentication enabled");
+        }
+        rpc_builder = rpc_builder.tls_config(tls_config)?;
+    }
+    // 同时提供 v1 和 v1alpha 两个版本的反射服务，兼容只支持旧版本协议的工具
+    let (reflection_v1, reflection_v1alpha) = if config.grpc_reflection {
+        tracing::info!("RPC server reflection enabled");
+        let builder = || {
+            tonic_reflection::server::Builder::configure()
+                .register_encoded_file_descriptor_set(common::DESCRIPTOR_SET)
+        };
+        (
+            Some(builder().build_v1()?),
+            Some(builder().build_v1alpha()?),
+        )
+    } else {
+        (None, None)
+    };
+
+    // 同一主机上的探针可以通过 Unix 域套接字连接，与 TCP 端口共用同一个服务实例
+    #[cfg(unix)]
+    let uds_server = match &config.rpc_uds_path {
+        Some(path) => {
+            let listener = bind_uds(path)?;
+            tracing::info!("Starting RPC server on unix://{}...", path);
+            let mut shutdown_rx = rpc_shutdown_rx.clone();
+            Some(
+                TonicServer::builder()
+                    .layer(receive_error_layer())
+                    .add_service(rpc_service.clone())
+                    .add_optional_service(reflection_v1.clone())
+                    .add_optional_service(reflection_v1alpha.clone())
+                    .serve_with_incoming_shutdown(listener, async move {
+                        let _ = shutdown_rx.changed().await;
+                    }),
+            )
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    let uds_server: Option<std::future::Ready<Result<(), tonic::transport::Error>>> = None;
+
+    let mut shutdown_rx = rpc_shutdown_rx;
+    let rpc_server = rpc_builder
         .add_service(rpc_service)
-        .serve(rpc_addr);
+        .add_optional_service(reflection_v1)
+        .add_optional_service(reflection_v1alpha)
+        .serve_with_incoming_shutdown(TcpListenerStream::new(rpc_listener), async move {
+            let _ = shutdown_rx.changed().await;
+        });
+    let uds_server = async {
+        match uds_server {
+            Some(server) => server.await,
+            None => Ok(()),
+        }
+    };
+
+    // 仪表盘请求的租户认证
+    let auth = TenantAuth::new(config.jwt_secret.as_deref());
+    if config.jwt_secret.is_none() {
+        tracing::info!("未配置 JWT 密钥，仪表盘请求不校验令牌，只能访问默认租户");
+    }
 
     // 创建路由
     let router = Router::new()
-        .push(Router::with_path("/ws").goal(WsHandler::new(command_tx)));
-    tracing::info!("Starting HTTP server...");
-    let acceptor = TcpListener::new("0.0.0.0:8000").bind().await;
+        .push(
+            Router::with_path("/health")
+                .get(HealthHandler::new(shared_states.clone(), state_check_task)),
+        )
+        .push(Router::with_path("/ws").goal(WsHandler::new(
+            command_tx.clone(),
+            shared_states.clone(),
+            config.serialize,
+            auth.clone(),
+        )))
+        .push(
+            Router::with_path("/api/servers")
+                .get(ServerListHandler::new(shared_states.clone(), auth.clone())),
+        )
+        .push(
+            Router::with_path("/api/servers/<id>/states")
+                .get(StateHistoryHandler::new(storage, auth.clone())),
+        )
+        .push(
+            Router::with_path("/api/servers/<id>/history")
+                .get(RecentStatesHandler::new(shared_states.clone(), auth)),
+        );
+    tracing::info!("Starting HTTP server on {}...", config.http_addr);
     // 启动 HTTP 服务器
-    let http_server = Server::new(acceptor).serve(router);
+    let http_server = Server::new(acceptor);
+    let http_handle = http_server.handle();
+    // 服务级中间件在未匹配路由时也会执行，可以处理任意路径的预检请求
+    let mut service = Service::new(router);
+    if !config.cors_origins.is_empty() {
+        tracing::info!("CORS enabled for {:?}", config.cors_origins);
+        service = service.hoop(CorsHandler::new(config.cors_origins.clone()));
+    }
+    let http_server = http_server.serve(service);
+
+    // 收到退出信号后通知仪表盘与探针下线，再优雅关闭两个服务器
+    let shutdown_task = tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("收到退出信号，正在关闭服务...");
+        let server_ids = shared_states.lock().await.server_ids();
+        // 没有仪表盘或探针订阅时发送会失败，可以忽略
+        let _ = command_tx.send(Command::new(CommandType::Offline, server_ids));
+        http_handle.stop_graceful(Duration::from_secs(GRACEFUL_SHUTDOWN_TIMEOUT_SECS));
+        let _ = rpc_shutdown_tx.send(());
+        Instant::now()
+    });
 
-    // 并发运行两个服务器
-    let _ = tokio::join!(rpc_server, http_server);
+    // 并发运行所有服务器
+    let _ = tokio::join!(rpc_server, uds_server, http_server);
+    #[cfg(unix)]
+    if let Some(path) = &config.rpc_uds_path {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // 服务器关闭后写入队列的发送端全部释放，等待后台任务写完剩余的状态
+    match tokio::time::timeout(
+        Duration::from_secs(GRACEFUL_SHUTDOWN_TIMEOUT_SECS),
+        state_writer_task,
+    )
+    .await
+    {
+        Ok(_) => tracing::debug!("状态写入队列已清空"),
+        Err(_) => tracing::warn!("等待状态写入超时，部分状态未保存"),
+    }
+
+    if shutdown_task.is_finished() {
+        if let Ok(start) = shutdown_task.await {
+            tracing::info!("服务已关闭，连接排空耗时 {:?}", start.elapsed());
+        }
+    }
 
     Ok(())
 }
+
+/// 初始化日志，日志级别由 `RUST_LOG` 控制
+fn init_logging(log_format: LogFormat) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// 启动定期聚合历史状态的后台任务，聚合失败只记录日志，下一轮重试
+fn spawn_rollup_task(storage: Storage, retention_raw_secs: u64, retention_rollup_secs: u64) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(ROLLUP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs());
+            let minute_before =
+                (retention_rollup_secs != 0).then(|| now.saturating_sub(retention_rollup_secs));
+            match storage
+                .rollup(now.saturating_sub(retention_raw_secs), minute_before)
+                .await
+            {
+                Ok(result) => tracing::debug!(
+                    raw_pruned = result.raw_pruned,
+                    minute_pruned = result.minute_pruned,
+                    "历史状态聚合完成"
+                ),
+                Err(e) => tracing::error!("聚合历史状态失败: {}", e),
+            }
+        }
+    });
+}
+
+/// 绑定 Unix 域套接字，路径上残留的套接字文件会先删除，其他类型的文件视为配置错误
+#[cfg(unix)]
+fn bind_uds(path: &str) -> anyhow::Result<tokio_stream::wrappers::UnixListenerStream> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+            .map_err(|e| anyhow::anyhow!("删除残留的套接字文件 {} 失败: {}", path, e))?,
+        Ok(_) => return Err(anyhow::anyhow!("{} 已存在且不是套接字文件", path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow::anyhow!("读取 {} 失败: {}", path, e)),
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| anyhow::anyhow!("RPC 服务无法监听 unix://{}: {}", path, e))?;
+    Ok(tokio_stream::wrappers::UnixListenerStream::new(listener))
+}
+
+/// 加载 RPC 服务的 TLS 配置，未配置证书时返回 None，使用明文传输
+fn load_tls_config(config: &Config) -> anyhow::Result<Option<ServerTlsConfig>> {
+    let (Some(cert_path), Some(key_path)) = (&config.tls_cert_path, &config.tls_key_path) else {
+        return Ok(None);
+    };
+    let cert = std::fs::read(cert_path)
+        .map_err(|e| anyhow::anyhow!("读取 TLS 证书 {} 失败: {}", cert_path, e))?;
+    let key = std::fs::read(key_path)
+        .map_err(|e| anyhow::anyhow!("读取 TLS 私钥 {} 失败: {}", key_path, e))?;
+    let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+    // 配置客户端 CA 后要求探针提供证书（mTLS），未认证的连接会在握手阶段被拒绝
+    if let Some(ca_path) = &config.tls_client_ca_path {
+        let ca = std::fs::read(ca_path)
+            .map_err(|e| anyhow::anyhow!("读取客户端 CA 证书 {} 失败: {}", ca_path, e))?;
+        tls_config = tls_config.client_ca_root(Certificate::from_pem(ca));
+    }
+    Ok(Some(tls_config))
+}
+
+/// 等待退出信号（Ctrl+C，Unix 下还包括 SIGTERM）
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("监听 Ctrl+C 信号失败");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("监听 SIGTERM 信号失败")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
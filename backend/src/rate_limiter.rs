@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::rpc_service::ServerKey;
+
+// 常量定义
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60); // 清理空闲令牌桶的最短间隔
+
+/// 单个探针的令牌桶
+#[derive(Debug)]
+struct Bucket {
+    /// 当前可用令牌数
+    tokens: f64,
+    /// 上一次补充令牌的时间
+    last_refill: Instant,
+    /// 本轮限流期间被拒绝的请求数，为 0 表示未处于限流状态
+    rejected: u64,
+}
+
+/// 所有探针的令牌桶
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<ServerKey, Bucket>,
+    /// 上一次清理空闲令牌桶的时间
+    last_sweep: Instant,
+}
+
+/// 按探针划分的令牌桶限流器，不同租户的相同探针 ID 分别限流
+/// 每个探针每秒补充 `rate` 个令牌，桶容量同为 `rate`（至少为 1），允许短时突发
+/// 空闲到令牌补满的桶与新建的桶没有区别，定期清理，避免下线的探针一直占用内存
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// 创建限流器，`rate` 为 0 时不限流
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            capacity: rate.max(1.0),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// 尝试为探针消耗一个令牌，令牌不足时返回 false
+    /// 探针开始被限流和恢复时各输出一条日志，避免限流期间刷屏
+    pub fn check(&self, key: &ServerKey) -> bool {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &ServerKey, now: Instant) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if now.duration_since(buckets.last_sweep) >= SWEEP_INTERVAL {
+            self.sweep(&mut buckets, now);
+        }
+        let server_id = key.server_id;
+        let bucket = buckets.buckets.entry(key.clone()).or_insert(Bucket {
+            tokens: self.capacity,
+            last_refill: now,
+            rejected: 0,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens < 1.0 {
+            if bucket.rejected == 0 {
+                tracing::warn!(
+                    server_id,
+                    "探针 {} 上报过于频繁，超过每秒 {} 次，开始限流",
+                    server_id,
+                    self.rate
+                );
+            }
+            bucket.rejected += 1;
+            return false;
+        }
+
+        if bucket.rejected > 0 {
+            tracing::info!(
+                server_id,
+                rejected = bucket.rejected,
+                "探针 {} 恢复正常上报，限流期间拒绝 {} 次请求",
+                server_id,
+                bucket.rejected
+            );
+            bucket.rejected = 0;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// 移除令牌已经补满的桶
+    fn sweep(&self, buckets: &mut Buckets, now: Instant) {
+        buckets.buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens + elapsed * self.rate < self.capacity
+        });
+        buckets.last_sweep = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket_count(limiter: &RateLimiter) -> usize {
+        limiter.buckets.lock().unwrap().buckets.len()
+    }
+
+    #[test]
+    fn flooding_server_does_not_affect_others() {
+        let limiter = RateLimiter::new(5.0);
+        let flooding = ServerKey::new("", 1);
+        let normal = ServerKey::new("", 2);
+        let start = Instant::now();
+
+        // 桶容量为 5，第 6 次请求被拒绝
+        for _ in 0..5 {
+            assert!(limiter.check_at(&flooding, start));
+        }
+        for _ in 0..100 {
+            assert!(!limiter.check_at(&flooding, start));
+        }
+        // 正常频率上报的探针不受影响
+        for second in 0..3 {
+            assert!(limiter.check_at(&normal, start + Duration::from_secs(second)));
+        }
+        // 一秒后补充 5 个令牌
+        let refilled = start + Duration::from_secs(1);
+        for _ in 0..5 {
+            assert!(limiter.check_at(&flooding, refilled));
+        }
+        assert!(!limiter.check_at(&flooding, refilled));
+    }
+
+    #[test]
+    fn same_server_id_in_other_tenant_is_limited_separately() {
+        let limiter = RateLimiter::new(1.0);
+        let start = Instant::now();
+
+        assert!(limiter.check_at(&ServerKey::new("a", 1), start));
+        assert!(!limiter.check_at(&ServerKey::new("a", 1), start));
+        assert!(limiter.check_at(&ServerKey::new("b", 1), start));
+    }
+
+    #[test]
+    fn idle_buckets_are_evicted() {
+        let limiter = RateLimiter::new(1.0);
+        let start = Instant::now();
+        for server_id in 0..100 {
+            limiter.check_at(&ServerKey::new("", server_id), start);
+        }
+        assert_eq!(bucket_count(&limiter), 100);
+
+        // 清理时只保留仍在补充令牌的桶
+        let later = start + SWEEP_INTERVAL;
+        limiter.check_at(&ServerKey::new("", 0), later - Duration::from_millis(500));
+        limiter.check_at(&ServerKey::new("", 1), later);
+        assert_eq!(bucket_count(&limiter), 2);
+    }
+
+    #[test]
+    fn zero_rate_disables_limit() {
+        let limiter = RateLimiter::new(0.0);
+        let key = ServerKey::new("", 1);
+        let start = Instant::now();
+
+        assert!((0..1000).all(|_| limiter.check_at(&key, start)));
+        assert_eq!(bucket_count(&limiter), 0);
+    }
+}
@@ -1,62 +1,653 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::{Mutex, Notify};
 
 use common::panda_monitor::{
-    panda_monitor_server::PandaMonitor, Command, CommandRequest, HostRequest, ServerResponse,
-    State, StateRequest, UpdateIpRequest,
+    panda_monitor_server::PandaMonitor, AgentInfo, Command, CommandAck, CommandRequest,
+    CommandType, HostRequest, ServerResponse, State, StateRequest, UpdateIpRequest,
 };
 use futures_util::StreamExt;
+use serde::Serialize;
 use tokio::sync::broadcast::Sender;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{Request, Response, Status, Streaming};
+use tonic::body::BoxBody;
+use tonic::codegen::http;
+use tonic::{Code, Request, Response, Status, Streaming};
+use tower::util::MapResponseLayer;
 
-/// 共享状态
-#[derive(Debug)]
-pub struct SharedState {
+use crate::alert::AlertEngine;
+use crate::codec::Serialization;
+use crate::config::Config;
+use crate::rate_limiter::RateLimiter;
+use crate::storage::StateWriter;
+use common::version::Version;
+
+/// 探针在共享状态中的键，不同租户的探针 ID 互相独立
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ServerKey {
+    /// 租户 ID，为空时为默认租户
+    pub tenant_id: String,
+    pub server_id: u64,
+}
+
+impl ServerKey {
+    pub fn new(tenant_id: &str, server_id: u64) -> Self {
+        Self {
+            tenant_id: tenant_id.to_string(),
+            server_id,
+        }
+    }
+}
+
+impl From<&AgentInfo> for ServerKey {
+    fn from(agent_info: &AgentInfo) -> Self {
+        Self::new(&agent_info.tenant_id, agent_info.server_id)
+    }
+}
+
+/// 探针最新状态
+#[derive(Debug, Clone)]
+pub struct ServerStatus {
+    /// 最近一次上报的状态
+    pub state: State,
+    /// 最近一次上报时间（秒）
+    pub last_seen: u64,
+    /// 最近一次状态中探针填写的上报时间（秒），用于识别重复的状态
+    pub upload_time: u64,
+}
+
+/// 探针快照，用于 WebSocket 连接建立时下发
+#[derive(Debug, Serialize)]
+pub struct ServerSnapshot {
+    pub server_id: u64,
+    pub state: State,
+    pub last_seen: u64,
+    /// 距最近一次上报的秒数，按服务端时钟计算，客户端无需依赖本地时钟
+    pub seconds_since_last_report: u64,
+    pub online: bool,
+}
+
+/// 内存中保留的最近状态
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    /// 探针上报时间（秒）
+    pub timestamp: u64,
+    pub state: State,
+}
+
+/// 已知探针，用于 WebSocket 的 `list` 消息
+#[derive(Debug, Serialize)]
+pub struct ServerListItem {
+    pub server_id: u64,
+    pub last_seen: u64,
+    /// 距最近一次上报的秒数，按服务端时钟计算，客户端无需依赖本地时钟
+    pub seconds_since_last_report: u64,
+    pub online: bool,
+    /// 探针随主机信息上报的标签，尚未上报主机信息时为空
+    pub labels: HashMap<String, String>,
+    /// 最近一次命令确认，探针尚未确认过命令时为空
+    pub last_ack: Option<LastAck>,
+}
+
+/// 探针最近一次的命令确认
+#[derive(Debug, Clone, Serialize)]
+pub struct LastAck {
+    /// 命令类型，为小写的命令名称（如 `set_interval`）
+    pub command_type: String,
+    pub success: bool,
+    /// 收到确认的时间（秒）
+    pub timestamp: u64,
+}
+
+/// 探针 ID 的连接来源
+#[derive(Debug, Clone, Copy)]
+struct PeerClaim {
+    /// 最近一次连接的地址
+    addr: SocketAddr,
+    /// 最近一次请求时间（秒）
+    last_seen: u64,
+}
+
+/// 等待广播的状态
+#[derive(Debug, Default)]
+struct PendingStates {
     /// 探针状态
     states: Vec<State>,
     /// 探针ID
     server_ids: HashSet<u64>,
 }
 
+/// 共享状态，探针按 (租户 ID, 探针 ID) 区分
+#[derive(Debug)]
+pub struct SharedState {
+    /// 每个租户等待广播的状态，按租户分别广播
+    pending: HashMap<String, PendingStates>,
+    /// 每个探针的最新状态
+    servers: HashMap<ServerKey, ServerStatus>,
+    /// 每个探针 ID 的连接来源
+    peers: HashMap<ServerKey, PeerClaim>,
+    /// 每个探针最近的状态，按上报顺序排列
+    history: HashMap<ServerKey, VecDeque<HistoryEntry>>,
+    /// 每个探针最多保留的状态条数
+    history_len: usize,
+    /// 每个探针最近一次主机信息中的标签
+    labels: HashMap<ServerKey, HashMap<String, String>>,
+    /// 每个探针当前打开的命令流数量，只有命令流打开的探针才能收到命令
+    connected: HashMap<ServerKey, usize>,
+    /// 每个探针最近一次主机信息中的开机时间
+    boot_times: HashMap<ServerKey, u64>,
+    /// 每个探针最近一次的命令确认
+    acks: HashMap<ServerKey, LastAck>,
+}
+
 impl SharedState {
-    pub fn new() -> Self {
+    pub fn new(history_len: usize) -> Self {
         Self {
-            states: Vec::new(),
-            server_ids: HashSet::new(),
+            pending: HashMap::new(),
+            servers: HashMap::new(),
+            peers: HashMap::new(),
+            history: HashMap::new(),
+            history_len,
+            labels: HashMap::new(),
+            connected: HashMap::new(),
+            boot_times: HashMap::new(),
+            acks: HashMap::new(),
+        }
+    }
+
+    /// 判断状态是否重复，重试或重放会重新发送已接收的状态
+    /// 上报时间早于最近一次状态时视为重复；上报时间为秒级，亚秒级上报间隔下同一秒内有多条状态，
+    /// 因此时间相同时只有内容也相同才视为重复
+    fn is_duplicate(&self, key: &ServerKey, upload_time: u64, state: &State) -> bool {
+        self.servers.get(key).is_some_and(|last| {
+            upload_time < last.upload_time
+                || (upload_time == last.upload_time && last.state == *state)
+        })
+    }
+
+    /// 记录探针主机的开机时间，与上一次相比发生变化时返回上一次的开机时间
+    /// 开机时间由时钟推算，变化不超过容差时视为时钟调整
+    fn update_boot_time(&mut self, key: &ServerKey, boot_time: u64) -> Option<u64> {
+        self.boot_times
+            .insert(key.clone(), boot_time)
+            .filter(|last| last.abs_diff(boot_time) > BOOT_TIME_TOLERANCE_SECS)
+    }
+
+    /// 记录探针打开了一个命令流
+    fn agent_connected(&mut self, key: &ServerKey) {
+        *self.connected.entry(key.clone()).or_default() += 1;
+    }
+
+    /// 记录探针最近一次的命令确认
+    fn record_ack(&mut self, key: &ServerKey, ack: &CommandAck) {
+        self.acks.insert(
+            key.clone(),
+            LastAck {
+                command_type: ack.command_type().as_str_name().to_ascii_lowercase(),
+                success: ack.success,
+                timestamp: now_secs(),
+            },
+        );
+    }
+
+    /// 记录探针关闭了一个命令流
+    fn agent_disconnected(&mut self, key: &ServerKey) {
+        if let Some(count) = self.connected.get_mut(key) {
+            *count -= 1;
+            if *count == 0 {
+                self.connected.remove(key);
+            }
         }
     }
+
+    /// 获取租户当前打开命令流的探针 ID，按 ID 排序
+    pub fn connected_ids(&self, tenant_id: &str) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .connected
+            .keys()
+            .filter(|key| key.tenant_id == tenant_id)
+            .map(|key| key.server_id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// 把请求的租户探针 ID 分为已连接和未连接两组，请求为空时返回租户所有已连接的探针
+    pub fn split_connected(&self, tenant_id: &str, requested: &[u64]) -> (Vec<u64>, Vec<u64>) {
+        if requested.is_empty() {
+            return (self.connected_ids(tenant_id), Vec::new());
+        }
+        requested.iter().partition(|server_id| {
+            self.connected
+                .contains_key(&ServerKey::new(tenant_id, **server_id))
+        })
+    }
+
+    /// 记录探针的最新状态，并加入所属租户等待广播的状态
+    fn push_state(&mut self, key: &ServerKey, upload_time: u64, state: State) {
+        self.servers.insert(
+            key.clone(),
+            ServerStatus {
+                state: state.clone(),
+                last_seen: now_secs(),
+                upload_time,
+            },
+        );
+        self.push_history(key, upload_time, state.clone());
+        let pending = self.pending.entry(key.tenant_id.clone()).or_default();
+        pending.states.push(state);
+        pending.server_ids.insert(key.server_id);
+    }
+
+    /// 记录探针的最近状态，超出保留条数时丢弃最旧的记录
+    fn push_history(&mut self, key: &ServerKey, timestamp: u64, state: State) {
+        if self.history_len == 0 {
+            return;
+        }
+        let entries = self.history.entry(key.clone()).or_default();
+        if entries.len() == self.history_len {
+            entries.pop_front();
+        }
+        entries.push_back(HistoryEntry { timestamp, state });
+    }
+
+    /// 获取探针在内存中保留的最近状态，探针未上报过状态时返回 None
+    pub fn history(&self, key: &ServerKey) -> Option<Vec<HistoryEntry>> {
+        if !self.servers.contains_key(key) {
+            return None;
+        }
+        Some(
+            self.history
+                .get(key)
+                .map(|entries| entries.iter().cloned().collect())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// 登记探针 ID 的连接来源
+    /// 若该 ID 仍被其他 IP 的主机占用，返回占用者的地址。
+    /// 同一 IP 的重连不视为冲突，占用者离线后其他主机可以接管该 ID。
+    fn claim_server_id(&mut self, key: &ServerKey, addr: SocketAddr) -> Result<(), SocketAddr> {
+        let now = now_secs();
+        if let Some(claim) = self.peers.get(key) {
+            if claim.addr.ip() != addr.ip()
+                && now.saturating_sub(claim.last_seen) <= SERVER_OFFLINE_SECONDS
+            {
+                return Err(claim.addr);
+            }
+        }
+        self.peers.insert(
+            key.clone(),
+            PeerClaim {
+                addr,
+                last_seen: now,
+            },
+        );
+        Ok(())
+    }
+
+    /// 获取所有租户已上报过状态的探针ID
+    pub fn server_ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.servers.keys().map(|key| key.server_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// 获取在线探针数量
+    pub fn online_count(&self) -> usize {
+        let now = now_secs();
+        self.servers
+            .values()
+            .filter(|status| now.saturating_sub(status.last_seen) <= SERVER_OFFLINE_SECONDS)
+            .count()
+    }
+
+    /// 获取租户所有探针的最新状态快照
+    pub fn snapshot(&self, tenant_id: &str) -> Vec<ServerSnapshot> {
+        let now = now_secs();
+        self.servers
+            .iter()
+            .filter(|(key, _)| key.tenant_id == tenant_id)
+            .map(|(key, status)| ServerSnapshot {
+                server_id: key.server_id,
+                state: status.state.clone(),
+                last_seen: status.last_seen,
+                seconds_since_last_report: now.saturating_sub(status.last_seen),
+                online: now.saturating_sub(status.last_seen) <= SERVER_OFFLINE_SECONDS,
+            })
+            .collect()
+    }
+
+    /// 获取租户已上报过状态的探针及最近上报时间、标签，按探针 ID 排序
+    pub fn list_servers(&self, tenant_id: &str) -> Vec<ServerListItem> {
+        let now = now_secs();
+        let mut servers: Vec<ServerListItem> = self
+            .servers
+            .iter()
+            .filter(|(key, _)| key.tenant_id == tenant_id)
+            .map(|(key, status)| ServerListItem {
+                server_id: key.server_id,
+                last_seen: status.last_seen,
+                seconds_since_last_report: now.saturating_sub(status.last_seen),
+                online: now.saturating_sub(status.last_seen) <= SERVER_OFFLINE_SECONDS,
+                labels: self.labels.get(key).cloned().unwrap_or_default(),
+                last_ack: self.acks.get(key).cloned(),
+            })
+            .collect();
+        servers.sort_by_key(|server| server.server_id);
+        servers
+    }
+}
+
+/// 请求的来源，需要在取出请求体之前读取
+#[derive(Debug, Clone, Default)]
+struct Peer {
+    /// 远端地址，Unix 域套接字连接为 None
+    addr: Option<SocketAddr>,
+    /// 客户端证书声明的租户，未提供客户端证书时为 None
+    cert_tenant: Option<String>,
+}
+
+impl Peer {
+    fn of<T>(request: &Request<T>) -> Self {
+        Self {
+            addr: request.remote_addr(),
+            cert_tenant: request
+                .peer_certs()
+                .and_then(|certs| certs.first().map(|cert| cert_tenant(cert))),
+        }
+    }
+}
+
+/// 读取客户端证书主题中的组织（O）作为租户 ID，没有该字段或无法解析时为默认租户
+fn cert_tenant(der: &[u8]) -> String {
+    x509_parser::parse_x509_certificate(der)
+        .ok()
+        .and_then(|(_, cert)| {
+            cert.subject()
+                .iter_organization()
+                .next()
+                .and_then(|organization| organization.as_str().ok())
+                .map(str::to_string)
+        })
+        .unwrap_or_default()
+}
+
+/// 探针准入检查
+#[derive(Debug, Clone)]
+struct AgentGuard {
+    /// 最低兼容的探针版本
+    min_agent_version: Version,
+    /// 是否允许多个主机使用相同的探针 ID
+    allow_duplicate_ids: bool,
+    /// 是否开启了客户端证书认证，未开启时探针声明的租户无法验证，只允许默认租户
+    client_auth: bool,
+    shared_states: Arc<Mutex<SharedState>>,
+}
+
+impl AgentGuard {
+    /// 检查探针版本、租户 ID 和探针 ID 是否允许接入
+    async fn check(&self, agent_info: &AgentInfo, peer: &Peer) -> Result<(), Status> {
+        self.check_version(agent_info)?;
+        common::validate_tenant_id(&agent_info.tenant_id).map_err(Status::invalid_argument)?;
+        self.check_tenant(agent_info, peer)?;
+        self.check_duplicate_id(&ServerKey::from(agent_info), peer.addr)
+            .await
+    }
+
+    /// 检查探针声明的租户是否与客户端证书一致
+    /// 开启客户端证书认证时租户必须与证书主题中的组织（O）相同，没有证书的连接（如 Unix 域套接字）只能使用默认租户；
+    /// 未开启时无法确认探针身份，拒绝非默认租户，避免探针冒充其他租户上报
+    #[allow(clippy::result_large_err)]
+    fn check_tenant(&self, agent_info: &AgentInfo, peer: &Peer) -> Result<(), Status> {
+        if !self.client_auth {
+            if agent_info.tenant_id.is_empty() {
+                return Ok(());
+            }
+            tracing::warn!(
+                server_id = agent_info.server_id,
+                tenant_id = agent_info.tenant_id.as_str(),
+                "探针 {} 声明了租户 {}，但服务端未开启客户端证书认证，拒绝连接",
+                agent_info.server_id,
+                agent_info.tenant_id
+            );
+            return Err(Status::permission_denied(
+                "tenant requires client certificate authentication",
+            ));
+        }
+        let cert_tenant = peer.cert_tenant.as_deref().unwrap_or_default();
+        if agent_info.tenant_id != cert_tenant {
+            tracing::warn!(
+                server_id = agent_info.server_id,
+                tenant_id = agent_info.tenant_id.as_str(),
+                "探针 {} 声明的租户 {} 与客户端证书的租户 {} 不一致，拒绝连接",
+                agent_info.server_id,
+                agent_info.tenant_id,
+                cert_tenant
+            );
+            return Err(Status::permission_denied(
+                "tenant does not match client certificate",
+            ));
+        }
+        Ok(())
+    }
+
+    /// 检查探针版本是否满足最低兼容版本
+    #[allow(clippy::result_large_err)]
+    fn check_version(&self, agent_info: &AgentInfo) -> Result<(), Status> {
+        let compatible = agent_info
+            .agent_version
+            .parse::<Version>()
+            .is_ok_and(|version| version >= self.min_agent_version);
+        if !compatible {
+            tracing::warn!(
+                server_id = agent_info.server_id,
+                "探针 {} 版本 {} 低于最低兼容版本 {}",
+                agent_info.server_id,
+                agent_info.agent_version,
+                self.min_agent_version
+            );
+            return Err(Status::failed_precondition("agent too old"));
+        }
+        Ok(())
+    }
+
+    /// 检查探针 ID 是否已被租户内的其他主机占用
+    async fn check_duplicate_id(
+        &self,
+        key: &ServerKey,
+        remote_addr: Option<SocketAddr>,
+    ) -> Result<(), Status> {
+        let Some(addr) = remote_addr else {
+            return Ok(());
+        };
+        if self.allow_duplicate_ids {
+            return Ok(());
+        }
+        let mut states_lock = self.shared_states.lock().await;
+        if let Err(existing) = states_lock.claim_server_id(key, addr) {
+            tracing::warn!(
+                server_id = key.server_id,
+                tenant_id = key.tenant_id.as_str(),
+                "探针 ID {} 重复: 已被 {} 使用，拒绝来自 {} 的连接",
+                key.server_id,
+                existing,
+                addr
+            );
+            return Err(Status::already_exists("agent id already in use"));
+        }
+        Ok(())
+    }
+}
+
+/// 获取当前时间戳（秒）
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// 转换接收请求流时的错误
+/// 消息超过大小限制时 tonic 返回 OUT_OF_RANGE，统一转换为 RESOURCE_EXHAUSTED，其余错误返回 INTERNAL
+fn receive_error(e: Status) -> Status {
+    if e.code() == Code::OutOfRange {
+        Status::resource_exhausted(format!("请求消息过大: {}", e.message()))
+    } else {
+        Status::internal("接收请求失败")
+    }
+}
+
+/// 在响应中转换消息过大的错误，与 `receive_error` 保持一致
+/// 一元请求在进入处理函数之前解码，解码失败时错误不经过 `receive_error`
+fn map_receive_error(mut response: http::Response<BoxBody>) -> http::Response<BoxBody> {
+    if let Some(status) = Status::from_header_map(response.headers()) {
+        if status.code() == Code::OutOfRange {
+            let _ = receive_error(status).add_header(response.headers_mut());
+        }
+    }
+    response
+}
+
+pub type ReceiveErrorLayer =
+    MapResponseLayer<fn(http::Response<BoxBody>) -> http::Response<BoxBody>>;
+
+/// RPC 服务器的服务层，转换所有请求中消息过大的错误
+pub fn receive_error_layer() -> ReceiveErrorLayer {
+    MapResponseLayer::new(map_receive_error)
 }
 
 // 定义常量
-const COMMAND_TIMEOUT_SECONDS: u64 = 30; // 命令处理超时时间
-const MAX_SERVER_COUNT: usize = 50; // TODO: 暂时硬编码，最终从 websocket 中获取需要发送的探针 id 计算探针数量
+const COMMAND_TIMEOUT_SECONDS: u64 = 30; // 命令流无活动超时时间
+const SERVER_OFFLINE_SECONDS: u64 = 10; // 超过该时间未上报视为离线
+const ALERT_OFFLINE_CHECK_INTERVAL: Duration = Duration::from_secs(5); // 检查告警中的探针是否离线的间隔
+const BOOT_TIME_TOLERANCE_SECS: u64 = 10; // 开机时间的变化不超过该值时不视为重启
 
 #[derive(Debug)]
 pub struct PandaMonitorService {
     command_tx: Sender<Command>,
     shared_states: Arc<Mutex<SharedState>>,
     notify: Arc<Notify>,
+    state_writer: StateWriter,
+    state_check_task: Arc<JoinHandle<()>>,
+    guard: AgentGuard,
+    heartbeat_interval: Duration,
+    command_buffer: usize,
+    supported_agent_version: Version,
+    state_rate_limiter: RateLimiter,
+    max_clock_skew: u64,
+    /// 未配置告警规则时为 None
+    alert_engine: Option<Arc<AlertEngine>>,
 }
 
 impl PandaMonitorService {
-    pub fn new(command_tx: Sender<Command>) -> Self {
-        let service = Self {
-            command_tx,
-            shared_states: Arc::new(Mutex::new(SharedState::new())),
-            notify: Arc::new(Notify::new()),
-        };
+    /// 创建 RPC 服务，`shared_states` 由调用方创建，与 WebSocket 等只读方共享
+    pub fn new(
+        command_tx: Sender<Command>,
+        state_writer: StateWriter,
+        shared_states: Arc<Mutex<SharedState>>,
+        config: &Config,
+    ) -> anyhow::Result<Self> {
+        let notify = Arc::new(Notify::new());
 
         // 启动后台状态检查任务
-        Self::start_state_check_task(
-            service.shared_states.clone(),
-            service.command_tx.clone(),
-            service.notify.clone(),
+        let state_check_task = Self::start_state_check_task(
+            shared_states.clone(),
+            command_tx.clone(),
+            notify.clone(),
+            config.serialize,
+        );
+
+        let guard = AgentGuard {
+            min_agent_version: config.min_agent_version,
+            allow_duplicate_ids: config.allow_duplicate_ids,
+            client_auth: config.tls_client_ca_path.is_some(),
+            shared_states: shared_states.clone(),
+        };
+
+        let alert_engine = match &config.webhook_url {
+            Some(url) if !config.alert_rules.is_empty() => Some(Arc::new(AlertEngine::new(
+                url.clone(),
+                config.alert_rules.clone(),
+            )?)),
+            _ => None,
+        };
+        if let Some(alert_engine) = &alert_engine {
+            Self::start_alert_offline_task(alert_engine.clone());
+        }
+
+        Ok(Self {
+            command_tx,
+            shared_states,
+            notify,
+            state_writer,
+            state_check_task: Arc::new(state_check_task),
+            guard,
+            heartbeat_interval: Duration::from_secs(config.heartbeat_interval_secs),
+            command_buffer: config.command_buffer,
+            supported_agent_version: config.supported_agent_version,
+            state_rate_limiter: RateLimiter::new(config.state_rate_limit),
+            max_clock_skew: config.max_clock_skew_secs,
+            alert_engine,
+        })
+    }
+
+    /// 构建成功响应，附带建议的最低探针版本
+    fn success_response(&self) -> Response<ServerResponse> {
+        Response::new(ServerResponse {
+            success: true,
+            min_supported_version: self.supported_agent_version.to_string(),
+            message: String::new(),
+        })
+    }
+
+    /// 检查上报时间是否超前服务器时间过多，避免时钟错误的探针写入未来时间的数据
+    /// 超出允许范围时返回失败原因
+    fn check_upload_time(&self, server_id: u64, upload_time: u64) -> Result<(), String> {
+        let ahead = upload_time.saturating_sub(now_secs());
+        if ahead <= self.max_clock_skew {
+            return Ok(());
+        }
+        tracing::warn!(
+            server_id,
+            ahead_secs = ahead,
+            "探针 {} 的上报时间超前服务器 {} 秒，请检查该主机的时钟同步",
+            server_id,
+            ahead
         );
+        Err(format!(
+            "上报时间超前服务器 {} 秒，超过允许的 {} 秒，请检查时钟同步",
+            ahead, self.max_clock_skew
+        ))
+    }
+
+    /// 构建失败响应，`message` 说明失败原因
+    fn failure_response(&self, message: impl Into<String>) -> Response<ServerResponse> {
+        Response::new(ServerResponse {
+            success: false,
+            min_supported_version: self.supported_agent_version.to_string(),
+            message: message.into(),
+        })
+    }
 
-        service
+    /// 获取后台状态检查任务句柄，用于健康检查
+    pub fn state_check_task(&self) -> Arc<JoinHandle<()>> {
+        self.state_check_task.clone()
+    }
+
+    /// 启动定期解除离线探针告警的后台任务
+    fn start_alert_offline_task(alert_engine: Arc<AlertEngine>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ALERT_OFFLINE_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                alert_engine.resolve_offline(now_secs(), SERVER_OFFLINE_SECONDS);
+            }
+        });
     }
 
     /// 启动状态检查后台任务
@@ -64,42 +655,27 @@ impl PandaMonitorService {
         states: Arc<Mutex<SharedState>>,
         command_tx: Sender<Command>,
         notify: Arc<Notify>,
-    ) {
+        serialization: Serialization,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             loop {
                 notify.notified().await;
                 let mut states_lock = states.lock().await;
 
-                // 如果服务器数量达到上限，跳过处理
-                if states_lock.server_ids.len() == MAX_SERVER_COUNT {
-                    continue;
-                }
-
-                // 序列化状态信息
-                let serialized_data = match serde_json::to_string(&states_lock.states) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        tracing::error!("序列化状态信息失败: {}", e);
-                        String::new()
+                // 每个租户单独广播，广播后清空，序列化失败的状态直接丢弃，避免积压
+                for (tenant_id, pending) in states_lock.pending.drain() {
+                    let server_ids = pending.server_ids.into_iter().collect();
+                    match serialization.state_update(&pending.states, server_ids) {
+                        Ok(command) => {
+                            if let Err(e) = command_tx.send(command.with_tenant(&tenant_id)) {
+                                tracing::error!("转发状态信息失败: {}", e);
+                            }
+                        }
+                        Err(e) => tracing::error!("序列化状态信息失败: {}", e),
                     }
-                };
-
-                // 构建命令并发送
-                let command = Command {
-                    command: 1,
-                    data: serialized_data,
-                    server_ids: states_lock.server_ids.iter().copied().collect(),
-                };
-
-                if let Err(e) = command_tx.send(command) {
-                    tracing::error!("转发状态信息失败: {}", e);
                 }
-
-                // 清理已处理的状态
-                states_lock.states.clear();
-                states_lock.server_ids.clear();
             }
-        });
+        })
     }
 }
 
@@ -109,63 +685,186 @@ impl PandaMonitor for PandaMonitorService {
         &self,
         request: Request<Streaming<HostRequest>>,
     ) -> Result<Response<ServerResponse>, Status> {
+        let peer = Peer::of(&request);
         let mut stream = request.into_inner();
         while let Some(request) = stream.next().await {
             let req = request.map_err(|e| {
                 tracing::error!("接收主机信息请求错误: {:?}", e);
-                Status::internal("接收请求失败")
+                receive_error(e)
             })?;
 
+            let agent_info = req
+                .agent_info
+                .ok_or(Status::invalid_argument("缺少探针信息"))?;
+            self.guard.check(&agent_info, &peer).await?;
+            if let Err(message) = self.check_upload_time(agent_info.server_id, req.upload_time) {
+                return Ok(self.failure_response(message));
+            }
+
             let host_info = req.host.ok_or(Status::invalid_argument("缺少主机信息"))?;
+            common::validate_labels(&host_info.labels).map_err(Status::invalid_argument)?;
             tracing::info!("存储主机信息: {:?}", host_info);
+            let key = ServerKey::from(&agent_info);
+            let mut states_lock = self.shared_states.lock().await;
+            // 探针随主机一起重启时无法自行发现，通过主机信息中的开机时间判断
+            if let Some(last) = states_lock.update_boot_time(&key, host_info.boot_time) {
+                tracing::warn!(
+                    server_id = agent_info.server_id,
+                    "探针 {} 所在主机已重启，开机时间 {} -> {}",
+                    agent_info.server_id,
+                    last,
+                    host_info.boot_time
+                );
+            }
+            if let Some(alert_engine) = &self.alert_engine {
+                alert_engine.update_host(&key, &host_info);
+            }
+            states_lock.labels.insert(key, host_info.labels);
+            drop(states_lock);
             // TODO: 实现数据库存储逻辑
         }
-        Ok(Response::new(ServerResponse { success: true }))
+        Ok(self.success_response())
     }
 
     async fn report_server_state(
         &self,
         request: Request<Streaming<StateRequest>>,
     ) -> Result<Response<ServerResponse>, Status> {
+        let peer = Peer::of(&request);
         let mut stream = request.into_inner();
         let shared_states = self.shared_states.clone();
+        let mut storage_failed = false;
+        let mut received = 0u64;
+        // 被拒绝的状态数量和最近一次拒绝的原因
+        let mut rejected = 0u64;
+        let mut last_rejection = String::new();
 
-        if let Some(request) = stream.next().await {
+        // 探针在一个流中持续发送状态，流结束时才返回响应
+        // 单条状态被拒绝时只丢弃该状态，不结束整个流，避免探针丢弃缓冲中的状态并重连
+        while let Some(request) = stream.next().await {
             let req = request.map_err(|e| {
                 tracing::error!("接收状态请求错误: {:?}", e);
-                Status::internal("接收请求失败")
+                receive_error(e)
             })?;
 
             let state = req.state.ok_or(Status::invalid_argument("缺少状态信息"))?;
             let agent_info = req
                 .agent_info
                 .ok_or(Status::invalid_argument("缺少探针信息"))?;
+            self.guard.check(&agent_info, &peer).await?;
+            let key = ServerKey::from(&agent_info);
+            if !self.state_rate_limiter.check(&key) {
+                rejected += 1;
+                last_rejection = "状态上报过于频繁".to_string();
+                continue;
+            }
+            if let Err(message) = self.check_upload_time(agent_info.server_id, req.upload_time) {
+                rejected += 1;
+                last_rejection = message;
+                continue;
+            }
+            // 重复的状态不再写入，仍返回成功，避免探针继续重试
+            if shared_states
+                .lock()
+                .await
+                .is_duplicate(&key, req.upload_time, &state)
+            {
+                tracing::debug!(
+                    server_id = agent_info.server_id,
+                    upload_time = req.upload_time,
+                    "丢弃探针 {} 的重复状态",
+                    agent_info.server_id
+                );
+                continue;
+            }
+
+            if let Err(e) =
+                self.state_writer
+                    .write(&key.tenant_id, key.server_id, req.upload_time, &state)
+            {
+                tracing::error!(
+                    server_id = agent_info.server_id,
+                    "保存探针 {} 状态失败: {}",
+                    agent_info.server_id,
+                    e
+                );
+                storage_failed = true;
+            }
+
+            if let Some(alert_engine) = &self.alert_engine {
+                alert_engine.observe(&key, req.upload_time, &state);
+            }
+            if state.rebooted_since_last_report {
+                tracing::warn!(
+                    server_id = agent_info.server_id,
+                    "探针 {} 报告主机在两次状态上报之间重启",
+                    agent_info.server_id
+                );
+            }
 
-            let mut states_lock = shared_states.lock().await;
-            states_lock.states.push(state);
-            states_lock.server_ids.insert(agent_info.server_id);
+            // 保存失败时仍更新实时状态，前端展示不受影响
+            shared_states
+                .lock()
+                .await
+                .push_state(&key, req.upload_time, state);
             self.notify.notify_one();
+            received += 1;
         }
+        tracing::debug!(
+            received,
+            rejected,
+            "状态上报流结束，共收到 {} 条状态，拒绝 {} 条",
+            received,
+            rejected
+        );
 
-        Ok(Response::new(ServerResponse { success: true }))
+        if storage_failed {
+            return Ok(self.failure_response("状态保存失败"));
+        }
+        if rejected > 0 {
+            return Ok(self.failure_response(format!(
+                "{} 条状态被拒绝，最近一次原因: {}",
+                rejected, last_rejection
+            )));
+        }
+        Ok(self.success_response())
     }
 
     async fn update_ip(
         &self,
         request: Request<UpdateIpRequest>,
     ) -> Result<Response<ServerResponse>, Status> {
+        let peer = Peer::of(&request);
         let req = request.into_inner();
         tracing::info!("收到IP更新请求: {:?}", req);
 
         let agent_info = req
             .agent_info
             .ok_or(Status::invalid_argument("缺少探针信息"))?;
+        self.guard.check(&agent_info, &peer).await?;
         let server_id = agent_info.server_id;
-        let ip = req.ipv4;
 
-        tracing::info!("更新服务器 {} 的IP地址为 {}", server_id, ip);
+        if let Err(message) = self.check_upload_time(server_id, req.upload_time) {
+            return Ok(self.failure_response(message));
+        }
+        if req.ipv4.is_empty() && req.ipv6.is_empty() {
+            return Ok(self.failure_response("IPv4 和 IPv6 地址均为空"));
+        }
+        if !req.ipv4.is_empty() && req.ipv4.parse::<Ipv4Addr>().is_err() {
+            return Ok(self.failure_response(format!("无效的 IPv4 地址: {}", req.ipv4)));
+        }
+        if !req.ipv6.is_empty() && req.ipv6.parse::<Ipv6Addr>().is_err() {
+            return Ok(self.failure_response(format!("无效的 IPv6 地址: {}", req.ipv6)));
+        }
+
+        tracing::info!(
+            server_id,
+            "更新服务器 {} 的IP地址为 {}",
+            server_id,
+            req.ipv4
+        );
         // TODO: 实现IP更新逻辑
-        Ok(Response::new(ServerResponse { success: true }))
+        Ok(self.success_response())
     }
 
     type SendCommandStream = ReceiverStream<Result<Command, Status>>;
@@ -176,35 +875,82 @@ impl PandaMonitor for PandaMonitorService {
     ) -> Result<Response<Self::SendCommandStream>, Status> {
         tracing::info!("收到命令请求");
         let mut command_rx = self.command_tx.subscribe();
+        let peer = Peer::of(&request);
         let mut stream = request.into_inner();
-        let (tx, rx) = mpsc::channel(128);
+        let (tx, rx) = mpsc::channel(self.command_buffer);
         let response_stream = ReceiverStream::new(rx);
+        let guard = self.guard.clone();
+        let heartbeat_interval = self.heartbeat_interval;
+        let shared_states = self.shared_states.clone();
 
         tokio::spawn(async move {
             let timeout = tokio::time::Duration::from_secs(COMMAND_TIMEOUT_SECONDS);
-            let start = tokio::time::Instant::now();
+            let mut deadline = tokio::time::Instant::now() + timeout;
+            let mut heartbeat = tokio::time::interval_at(
+                tokio::time::Instant::now() + heartbeat_interval,
+                heartbeat_interval,
+            );
 
+            // 探针通过首个请求表明身份后登记为已连接，命令流结束时注销
+            let mut connected_key: Option<ServerKey> = None;
+
+            // 命令流有任何活动（包括心跳）都会重置超时时间
             loop {
                 tokio::select! {
                     Ok(command) = command_rx.recv() => {
+                        let offline = command.command() == CommandType::Offline;
+                        // 只转发探针所属租户的命令，探针表明身份前不转发；服务端下线命令发送给所有租户
+                        let same_tenant = connected_key
+                            .as_ref()
+                            .is_some_and(|key| key.tenant_id == command.tenant_id);
+                        if !offline && !same_tenant {
+                            continue;
+                        }
                         if tx.send(Ok(command)).await.is_err() {
                             tracing::error!("转发WebSocket命令失败");
                             break;
                         }
+                        // 服务端下线时结束命令流，便于优雅关闭
+                        if offline {
+                            break;
+                        }
+                        deadline = tokio::time::Instant::now() + timeout;
                     }
                     Some(request) = stream.next() => {
-                        if let Err(e) = Self::handle_grpc_command(&tx, request).await {
-                            tracing::error!("处理gRPC命令失败: {:?}", e);
+                        match Self::handle_grpc_command(&tx, request, &guard, &peer, &shared_states).await {
+                            Ok(key) => {
+                                if connected_key.is_none() {
+                                    shared_states.lock().await.agent_connected(&key);
+                                    connected_key = Some(key);
+                                }
+                            }
+                            Err(e) => {
+                                tracing::error!("处理gRPC命令失败: {:?}", e);
+                                let _ = tx.send(Err(e)).await;
+                                break;
+                            }
+                        }
+                        deadline = tokio::time::Instant::now() + timeout;
+                    }
+                    _ = heartbeat.tick() => {
+                        // 发送失败说明探针已断开
+                        if tx.send(Ok(Command::new(CommandType::Heartbeat, Vec::new()))).await.is_err() {
+                            tracing::info!("命令流已断开");
                             break;
                         }
+                        deadline = tokio::time::Instant::now() + timeout;
                     }
-                    _ = tokio::time::sleep_until(start + timeout) => {
+                    _ = tokio::time::sleep_until(deadline) => {
                         tracing::warn!("命令处理超时");
                         break;
                     }
                     else => break,
                 }
             }
+
+            if let Some(key) = connected_key {
+                shared_states.lock().await.agent_disconnected(&key);
+            }
         });
 
         Ok(Response::new(response_stream))
@@ -212,26 +958,336 @@ impl PandaMonitor for PandaMonitorService {
 }
 
 impl PandaMonitorService {
+    /// 处理探针在命令流中发送的请求，检查通过后返回探针的键
+    /// 命令确认只记录下来，其余请求回复连接确认
     async fn handle_grpc_command(
         tx: &mpsc::Sender<Result<Command, Status>>,
         request: Result<CommandRequest, Status>,
-    ) -> Result<(), Status> {
-        let req = request?;
+        guard: &AgentGuard,
+        peer: &Peer,
+        shared_states: &Mutex<SharedState>,
+    ) -> Result<ServerKey, Status> {
+        let req = request.map_err(receive_error)?;
         tracing::info!("收到gRPC命令: {:?}", req);
 
-        let command = Command {
-            command: 0,
-            data: "ok".into(),
-            server_ids: vec![
-                req.agent_info
-                    .ok_or(Status::invalid_argument("缺少探针信息"))?
-                    .server_id,
-            ],
-        };
+        let agent_info = req
+            .agent_info
+            .ok_or(Status::invalid_argument("缺少探针信息"))?;
+        guard.check(&agent_info, peer).await?;
+        let key = ServerKey::from(&agent_info);
+
+        if let Some(ack) = req.ack {
+            if ack.server_id != agent_info.server_id {
+                tracing::warn!(
+                    server_id = agent_info.server_id,
+                    "探针 {} 的命令确认中探针 ID 不一致: {}",
+                    agent_info.server_id,
+                    ack.server_id
+                );
+            } else {
+                shared_states.lock().await.record_ack(&key, &ack);
+            }
+            return Ok(key);
+        }
+
+        let command =
+            Command::new(CommandType::Connected, vec![key.server_id]).with_tenant(&key.tenant_id);
 
         tx.send(Ok(command))
             .await
             .map_err(|_| Status::internal("发送命令失败"))?;
-        Ok(())
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+    use clap::Parser;
+    use common::panda_monitor::panda_monitor_client::PandaMonitorClient;
+    use common::panda_monitor::panda_monitor_server::PandaMonitorServer;
+    use tokio::sync::broadcast;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    // 常量定义
+    const MAX_MESSAGE_SIZE: usize = 1024; // 测试服务端的消息大小限制
+                                          // 主题为 O=acme, CN=agent-1 的客户端证书
+    const ACME_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBmTCCAT+gAwIBAgIUFcX5eog35u85G9BuEY8h9zq0wS0wCgYIKoZIzj0EAwIw
+ITENMAsGA1UECgwEYWNtZTEQMA4GA1UEAwwHYWdlbnQtMTAgFw0yNjEwMTYxNDU0
+NTlaGA8yMTI2MDkyMjE0NTQ1OVowITENMAsGA1UECgwEYWNtZTEQMA4GA1UEAwwH
+YWdlbnQtMTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABCYcoOMytv6uA3pOC/6N
+4sunmqSk7txtZqKiHlcKtOmVXY7/BI3Y5oDy94pV++f3bvV/jw/uv/A33hNCIABr
+2O6jUzBRMB0GA1UdDgQWBBTGNDzHq5QPx5UH+XELhkH5Nvfi3jAfBgNVHSMEGDAW
+gBTGNDzHq5QPx5UH+XELhkH5Nvfi3jAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49
+BAMCA0gAMEUCIBnwkmYrz3GkT7I5Wrjumbo8stm9l2nZjc9dfoQXtx7ZAiEAkhjb
+4RW0EVhnl3H40Vu055nvSVqV6/+EdCGK4nTW37U=
+-----END CERTIFICATE-----";
+    // 主题只有 CN=agent-2 的客户端证书
+    const NO_ORGANIZATION_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBezCCASGgAwIBAgIURsK6pvVhPQDiOTgerDV9EtJnrbkwCgYIKoZIzj0EAwIw
+EjEQMA4GA1UEAwwHYWdlbnQtMjAgFw0yNjEwMTYxNDU0NTlaGA8yMTI2MDkyMjE0
+NTQ1OVowEjEQMA4GA1UEAwwHYWdlbnQtMjBZMBMGByqGSM49AgEGCCqGSM49AwEH
+A0IABCYcoOMytv6uA3pOC/6N4sunmqSk7txtZqKiHlcKtOmVXY7/BI3Y5oDy94pV
+++f3bvV/jw/uv/A33hNCIABr2O6jUzBRMB0GA1UdDgQWBBTGNDzHq5QPx5UH+XEL
+hkH5Nvfi3jAfBgNVHSMEGDAWgBTGNDzHq5QPx5UH+XELhkH5Nvfi3jAPBgNVHRMB
+Af8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIQCEQUp0eEiKTa+3F26qRF/dtZnP
+tPp1wmkE0p5ivw2YuQIgfc0IBO9d3JBI8xw3PLMcb3t/ZcLTBx5vr9WAIBQjkyk=
+-----END CERTIFICATE-----";
+
+    /// 在本地端口启动与 main 相同配置的 RPC 服务，返回客户端
+    async fn spawn_server() -> PandaMonitorClient<tonic::transport::Channel> {
+        let config = Config::parse_from(["backend"]);
+        let storage = Storage::connect_url("sqlite::memory:").await.unwrap();
+        let (state_writer, _) = storage.spawn_writer(config.storage_queue);
+        let (command_tx, _) = broadcast::channel(16);
+        let shared_states = Arc::new(Mutex::new(SharedState::new(config.state_history_len)));
+        let service =
+            PandaMonitorService::new(command_tx, state_writer, shared_states, &config).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .layer(receive_error_layer())
+                .add_service(
+                    PandaMonitorServer::new(service).max_decoding_message_size(MAX_MESSAGE_SIZE),
+                )
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        PandaMonitorClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+    }
+
+    fn agent_info() -> Option<AgentInfo> {
+        Some(AgentInfo {
+            agent_version: "0.1.0".to_string(),
+            server_id: 1,
+            ..Default::default()
+        })
+    }
+
+    fn state(cpu_usage: f64) -> State {
+        State {
+            cpu_usage,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn duplicate_states_are_detected() {
+        let mut shared_states = SharedState::new(0);
+        let key = ServerKey::new("", 1);
+        assert!(!shared_states.is_duplicate(&key, 100, &state(10.0)));
+        shared_states.push_state(&key, 100, state(10.0));
+
+        // 重试发送的相同状态
+        assert!(shared_states.is_duplicate(&key, 100, &state(10.0)));
+        // 早于最近一次状态的重放
+        assert!(shared_states.is_duplicate(&key, 99, &state(20.0)));
+        // 亚秒级上报间隔下同一秒内的不同状态
+        assert!(!shared_states.is_duplicate(&key, 100, &state(20.0)));
+        assert!(!shared_states.is_duplicate(&key, 101, &state(10.0)));
+        // 其他租户的相同探针 ID 互不影响
+        assert!(!shared_states.is_duplicate(&ServerKey::new("a", 1), 100, &state(10.0)));
+    }
+
+    #[tokio::test]
+    async fn large_batches_are_broadcast_per_tenant() {
+        // 常量定义
+        const SERVER_COUNT: u64 = 120; // 超过一次广播曾经的探针数量上限
+
+        let shared_states = Arc::new(Mutex::new(SharedState::new(0)));
+        let (command_tx, mut command_rx) = broadcast::channel(16);
+        let notify = Arc::new(Notify::new());
+        let _task = PandaMonitorService::start_state_check_task(
+            shared_states.clone(),
+            command_tx,
+            notify.clone(),
+            Serialization::Json,
+        );
+
+        for round in 0..2 {
+            {
+                let mut states_lock = shared_states.lock().await;
+                for server_id in 0..SERVER_COUNT {
+                    states_lock.push_state(&ServerKey::new("a", server_id), round, state(1.0));
+                }
+                states_lock.push_state(&ServerKey::new("b", 1), round, state(1.0));
+            }
+            notify.notify_one();
+
+            let mut tenants = Vec::new();
+            for _ in 0..2 {
+                let command = tokio::time::timeout(Duration::from_secs(5), command_rx.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                tenants.push((command.tenant_id.clone(), command.server_ids.len()));
+            }
+            tenants.sort();
+            assert_eq!(
+                tenants,
+                vec![
+                    ("a".to_string(), SERVER_COUNT as usize),
+                    ("b".to_string(), 1)
+                ]
+            );
+            assert!(shared_states.lock().await.pending.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_unary_request_is_resource_exhausted() {
+        let mut client = spawn_server().await;
+        let request = UpdateIpRequest {
+            ipv4: "1".repeat(MAX_MESSAGE_SIZE * 2),
+            agent_info: agent_info(),
+            ..Default::default()
+        };
+
+        let status = client.update_ip(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn oversized_stream_request_is_resource_exhausted() {
+        let mut client = spawn_server().await;
+        let request = StateRequest {
+            state: Some(State {
+                custom_metrics: HashMap::from([("x".repeat(MAX_MESSAGE_SIZE * 2), 1.0)]),
+                ..Default::default()
+            }),
+            agent_info: agent_info(),
+            upload_time: now_secs(),
+        };
+
+        let status = client
+            .report_server_state(tokio_stream::iter([request]))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn small_unary_request_is_handled() {
+        let mut client = spawn_server().await;
+        let request = UpdateIpRequest {
+            ipv4: "192.0.2.1".to_string(),
+            agent_info: agent_info(),
+            upload_time: now_secs(),
+            ..Default::default()
+        };
+
+        let response = client.update_ip(request).await.unwrap();
+        assert!(response.get_ref().success);
+    }
+
+    #[tokio::test]
+    async fn update_ip_checks_agent() {
+        let mut client = spawn_server().await;
+        let request = UpdateIpRequest {
+            ipv4: "192.0.2.1".to_string(),
+            agent_info: Some(tenant_agent("acme")),
+            upload_time: now_secs(),
+            ..Default::default()
+        };
+        let status = client.update_ip(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        let request = UpdateIpRequest {
+            ipv4: "192.0.2.1".to_string(),
+            agent_info: Some(AgentInfo {
+                agent_version: "0.0.1".to_string(),
+                server_id: 1,
+                ..Default::default()
+            }),
+            upload_time: now_secs(),
+            ..Default::default()
+        };
+        let status = client.update_ip(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    fn guard(client_auth: bool) -> AgentGuard {
+        AgentGuard {
+            min_agent_version: "0.1.0".parse().unwrap(),
+            allow_duplicate_ids: true,
+            client_auth,
+            shared_states: Arc::new(Mutex::new(SharedState::new(0))),
+        }
+    }
+
+    fn tenant_agent(tenant_id: &str) -> AgentInfo {
+        AgentInfo {
+            agent_version: "0.1.0".to_string(),
+            server_id: 1,
+            tenant_id: tenant_id.to_string(),
+        }
+    }
+
+    fn peer(cert_tenant: Option<&str>) -> Peer {
+        Peer {
+            addr: None,
+            cert_tenant: cert_tenant.map(str::to_string),
+        }
+    }
+
+    fn der(pem: &str) -> Vec<u8> {
+        x509_parser::pem::parse_x509_pem(pem.as_bytes())
+            .unwrap()
+            .1
+            .contents
+    }
+
+    #[test]
+    fn tenant_is_read_from_certificate_organization() {
+        assert_eq!(cert_tenant(&der(ACME_CERT)), "acme");
+        assert_eq!(cert_tenant(&der(NO_ORGANIZATION_CERT)), "");
+        assert_eq!(cert_tenant(b"not a certificate"), "");
+    }
+
+    #[tokio::test]
+    async fn tenant_must_match_client_certificate() {
+        let guard = guard(true);
+
+        assert!(guard
+            .check(&tenant_agent("acme"), &peer(Some("acme")))
+            .await
+            .is_ok());
+        assert!(guard
+            .check(&tenant_agent(""), &peer(Some("")))
+            .await
+            .is_ok());
+        // 冒充其他租户
+        let status = guard
+            .check(&tenant_agent("other"), &peer(Some("acme")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        // 证书属于某个租户时也不能使用默认租户
+        assert!(guard
+            .check(&tenant_agent(""), &peer(Some("acme")))
+            .await
+            .is_err());
+        // 没有客户端证书的连接只能使用默认租户
+        assert!(guard.check(&tenant_agent(""), &peer(None)).await.is_ok());
+        assert!(guard
+            .check(&tenant_agent("acme"), &peer(None))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn tenant_requires_client_auth() {
+        let guard = guard(false);
+
+        assert!(guard.check(&tenant_agent(""), &peer(None)).await.is_ok());
+        let status = guard
+            .check(&tenant_agent("acme"), &peer(None))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
     }
 }
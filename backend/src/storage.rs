@@ -0,0 +1,607 @@
+use common::panda_monitor::State;
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+use sqlx::Row;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// 常量定义
+const DEFAULT_DATABASE_URL: &str = "sqlite://panda_monitor.db?mode=rwc"; // 默认数据库地址
+const MINUTE_RESOLUTION: u64 = 60; // 分钟聚合的粒度（秒）
+const HOUR_RESOLUTION: u64 = 3600; // 小时聚合的粒度（秒）
+const WRITE_BATCH_SIZE: usize = 256; // 后台写入任务在一个事务中最多写入的状态数
+const ROLLUP_METRICS: [(&str, &str); 4] = [
+    ("cpu", "cpu_usage"),
+    ("mem", "mem_used"),
+    ("net_in", "net_in_speed"),
+    ("net_out", "net_out_speed"),
+]; // 聚合的指标，(列名前缀, 状态字段名)
+
+/// 已存储的状态记录
+#[derive(Debug, Serialize)]
+pub struct StateRecord {
+    /// 上传时间，聚合记录为时间桶的起始时间
+    pub upload_time: u64,
+    /// 探针状态，聚合记录只包含聚合指标的平均值，其余字段为 0
+    #[serde(flatten)]
+    pub state: State,
+    /// 聚合信息，原始记录没有该字段
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollup: Option<RollupInfo>,
+}
+
+/// 聚合指标在时间桶内的范围
+#[derive(Debug, Serialize)]
+pub struct MetricRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// 聚合记录的粒度、样本数和各指标的范围
+#[derive(Debug, Serialize)]
+pub struct RollupInfo {
+    /// 聚合粒度（秒）
+    pub resolution: u64,
+    /// 聚合的原始样本数
+    pub samples: u64,
+    pub cpu_usage: MetricRange,
+    pub mem_used: MetricRange,
+    pub net_in_speed: MetricRange,
+    pub net_out_speed: MetricRange,
+}
+
+/// 一次聚合处理的记录数
+#[derive(Debug, Default)]
+pub struct RollupResult {
+    /// 聚合后删除的原始状态数
+    pub raw_pruned: u64,
+    /// 合并到小时聚合后删除的分钟聚合数
+    pub minute_pruned: u64,
+}
+
+/// 等待写入的状态，数值已转换为数据库整数，状态已序列化
+#[derive(Debug)]
+struct PendingState {
+    tenant_id: String,
+    server_id: i64,
+    upload_time: i64,
+    state: String,
+}
+
+/// 状态写入队列的发送端
+/// 状态由后台任务批量写入数据库，上报处理不等待写入完成，数据库较慢时不会阻塞探针的状态流
+#[derive(Debug, Clone)]
+pub struct StateWriter {
+    tx: mpsc::Sender<PendingState>,
+}
+
+impl StateWriter {
+    /// 将状态放入写入队列
+    /// 探针 ID 或上报时间超出数据库整数范围、队列已满或后台任务已退出时返回错误，该状态不会被保存
+    pub fn write(
+        &self,
+        tenant_id: &str,
+        server_id: u64,
+        upload_time: u64,
+        state: &State,
+    ) -> anyhow::Result<()> {
+        let pending = PendingState {
+            tenant_id: tenant_id.to_string(),
+            server_id: sql_int(server_id, "探针ID")?,
+            upload_time: sql_int(upload_time, "上报时间")?,
+            state: serde_json::to_string(state)?,
+        };
+        self.tx.try_send(pending).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => anyhow::anyhow!("状态写入队列已满"),
+            mpsc::error::TrySendError::Closed(_) => anyhow::anyhow!("状态写入任务已退出"),
+        })
+    }
+}
+
+/// 状态持久化存储
+#[derive(Debug, Clone)]
+pub struct Storage {
+    pool: SqlitePool,
+}
+
+impl Storage {
+    /// 连接数据库并初始化表结构
+    /// 数据库地址从环境变量 `PANDA_DATABASE_URL` 读取，未配置时使用当前目录下的 `panda_monitor.db`
+    pub async fn connect() -> anyhow::Result<Self> {
+        let url = std::env::var("PANDA_DATABASE_URL")
+            .unwrap_or_else(|_| DEFAULT_DATABASE_URL.to_string());
+        Self::connect_url(&url).await
+    }
+
+    /// 连接指定地址的数据库并初始化表结构
+    pub async fn connect_url(url: &str) -> anyhow::Result<Self> {
+        let pool = SqlitePool::connect(url)
+            .await
+            .map_err(|e| anyhow::anyhow!("连接数据库 {} 失败: {}", url, e))?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS states (
+                server_id INTEGER NOT NULL,
+                upload_time INTEGER NOT NULL,
+                state TEXT NOT NULL,
+                tenant_id TEXT NOT NULL DEFAULT ''
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(&create_rollups_table("state_rollups"))
+            .execute(&pool)
+            .await?;
+        migrate_tenant_id(&pool).await?;
+        sqlx::query("DROP INDEX IF EXISTS idx_states_server_time")
+            .execute(&pool)
+            .await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_states_tenant_server_time
+             ON states (tenant_id, server_id, upload_time)",
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(Self { pool })
+    }
+
+    /// 启动后台写入任务，返回容量为 `capacity` 的写入队列和任务句柄
+    /// 任务每次取出队列中已有的状态（最多 `WRITE_BATCH_SIZE` 条）在一个事务中写入，写入失败只记录日志；
+    /// 所有发送端释放后写完队列中剩余的状态再退出
+    pub fn spawn_writer(&self, capacity: usize) -> (StateWriter, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel(capacity);
+        let storage = self.clone();
+        let task = tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(WRITE_BATCH_SIZE);
+            while rx.recv_many(&mut batch, WRITE_BATCH_SIZE).await > 0 {
+                if let Err(e) = storage.insert_states(&batch).await {
+                    tracing::error!("保存 {} 条状态失败: {}", batch.len(), e);
+                }
+                batch.clear();
+            }
+        });
+        (StateWriter { tx }, task)
+    }
+
+    /// 在一个事务中保存多条状态
+    async fn insert_states(&self, states: &[PendingState]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for pending in states {
+            sqlx::query(
+                "INSERT INTO states (tenant_id, server_id, upload_time, state) VALUES (?, ?, ?, ?)",
+            )
+            .bind(&pending.tenant_id)
+            .bind(pending.server_id)
+            .bind(pending.upload_time)
+            .bind(&pending.state)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// 判断租户的探针是否存在已存储的状态
+    pub async fn has_server(&self, tenant_id: &str, server_id: u64) -> anyhow::Result<bool> {
+        // 超出数据库整数范围的探针 ID 不可能被保存
+        let Ok(server_id) = i64::try_from(server_id) else {
+            return Ok(false);
+        };
+        let row = sqlx::query(
+            "SELECT 1 FROM states WHERE tenant_id = ? AND server_id = ?
+             UNION ALL SELECT 1 FROM state_rollups WHERE tenant_id = ? AND server_id = ?
+             LIMIT 1",
+        )
+        .bind(tenant_id)
+        .bind(server_id)
+        .bind(tenant_id)
+        .bind(server_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.is_some())
+    }
+
+    /// 查询时间范围内的状态
+    /// 已被聚合的时间段返回小时或分钟聚合记录，其余返回原始记录，
+    /// 按 `step` 秒分桶，每个桶只返回最早的一条记录，最多返回 `limit` 条；
+    /// 时间范围超出数据库整数范围的部分被截断
+    pub async fn query_states(
+        &self,
+        tenant_id: &str,
+        server_id: u64,
+        from: u64,
+        to: u64,
+        step: u64,
+        limit: u32,
+    ) -> anyhow::Result<Vec<StateRecord>> {
+        let mut records = Vec::new();
+        for resolution in [HOUR_RESOLUTION, MINUTE_RESOLUTION] {
+            records.extend(
+                self.query_rollups(tenant_id, server_id, resolution, from, to, step, limit)
+                    .await?,
+            );
+        }
+        records.extend(
+            self.query_raw_states(tenant_id, server_id, from, to, step, limit)
+                .await?,
+        );
+        records.sort_by_key(|record| record.upload_time);
+        // 不同粒度的记录在分桶边界附近可能落入同一个桶
+        records.dedup_by_key(|record| record.upload_time / step);
+        records.truncate(limit as usize);
+        Ok(records)
+    }
+
+    /// 把早于 `raw_before` 的原始状态按分钟聚合，早于 `minute_before` 的分钟聚合再按小时聚合，
+    /// 并删除已聚合的数据。截止时间按聚合粒度向下取整，保证只聚合完整的时间桶；
+    /// 截止时间之前补报的状态会合并到已有的聚合记录中
+    pub async fn rollup(
+        &self,
+        raw_before: u64,
+        minute_before: Option<u64>,
+    ) -> anyhow::Result<RollupResult> {
+        let raw_before = sql_bound(raw_before / MINUTE_RESOLUTION * MINUTE_RESOLUTION);
+        let mut tx = self.pool.begin().await?;
+        let mut result = RollupResult::default();
+
+        let aggregates = ROLLUP_METRICS
+            .iter()
+            .map(|(_, field)| {
+                let value = format!("COALESCE(json_extract(state, '$.{}'), 0)", field);
+                format!(", MIN({0}), MAX({0}), AVG({0})", value)
+            })
+            .collect::<String>();
+        sqlx::query(&format!(
+            "INSERT INTO state_rollups
+             SELECT tenant_id, server_id, ?, upload_time / ? * ?, COUNT(*){}
+             FROM states WHERE upload_time < ?
+             GROUP BY tenant_id, server_id, upload_time / ?
+             {}",
+            aggregates,
+            rollup_upsert()
+        ))
+        .bind(MINUTE_RESOLUTION as i64)
+        .bind(MINUTE_RESOLUTION as i64)
+        .bind(MINUTE_RESOLUTION as i64)
+        .bind(raw_before)
+        .bind(MINUTE_RESOLUTION as i64)
+        .execute(&mut *tx)
+        .await?;
+        result.raw_pruned = sqlx::query("DELETE FROM states WHERE upload_time < ?")
+            .bind(raw_before)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        if let Some(minute_before) = minute_before {
+            let minute_before = sql_bound(minute_before / HOUR_RESOLUTION * HOUR_RESOLUTION);
+            let aggregates = ROLLUP_METRICS
+                .iter()
+                .map(|(prefix, _)| {
+                    format!(
+                        ", MIN({0}_min), MAX({0}_max), SUM({0}_avg * samples) / SUM(samples)",
+                        prefix
+                    )
+                })
+                .collect::<String>();
+            sqlx::query(&format!(
+                "INSERT INTO state_rollups
+                 SELECT tenant_id, server_id, ?, bucket_time / ? * ?, SUM(samples){}
+                 FROM state_rollups WHERE resolution = ? AND bucket_time < ?
+                 GROUP BY tenant_id, server_id, bucket_time / ?
+                 {}",
+                aggregates,
+                rollup_upsert()
+            ))
+            .bind(HOUR_RESOLUTION as i64)
+            .bind(HOUR_RESOLUTION as i64)
+            .bind(HOUR_RESOLUTION as i64)
+            .bind(MINUTE_RESOLUTION as i64)
+            .bind(minute_before)
+            .bind(HOUR_RESOLUTION as i64)
+            .execute(&mut *tx)
+            .await?;
+            result.minute_pruned =
+                sqlx::query("DELETE FROM state_rollups WHERE resolution = ? AND bucket_time < ?")
+                    .bind(MINUTE_RESOLUTION as i64)
+                    .bind(minute_before)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+        }
+
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// 查询时间范围内指定粒度的聚合记录，每个 `step` 秒的桶取最早的一条
+    #[allow(clippy::too_many_arguments)]
+    async fn query_rollups(
+        &self,
+        tenant_id: &str,
+        server_id: u64,
+        resolution: u64,
+        from: u64,
+        to: u64,
+        step: u64,
+        limit: u32,
+    ) -> anyhow::Result<Vec<StateRecord>> {
+        let metric_columns = ROLLUP_METRICS
+            .iter()
+            .map(|(prefix, _)| format!(", {0}_min, {0}_max, {0}_avg", prefix))
+            .collect::<String>();
+        let rows = sqlx::query(&format!(
+            "SELECT bucket_time, samples{0} FROM (
+                 SELECT bucket_time, samples{0},
+                     ROW_NUMBER() OVER (PARTITION BY bucket_time / ? ORDER BY bucket_time) AS row_number
+                 FROM state_rollups
+                 WHERE tenant_id = ? AND server_id = ? AND resolution = ? AND bucket_time BETWEEN ? AND ?
+             )
+             WHERE row_number = 1
+             ORDER BY bucket_time
+             LIMIT ?",
+            metric_columns
+        ))
+        .bind(sql_bound(step))
+        .bind(tenant_id)
+        .bind(sql_int(server_id, "探针ID")?)
+        .bind(resolution as i64)
+        .bind(sql_bound(from))
+        .bind(sql_bound(to))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let bucket_time: i64 = row.try_get("bucket_time")?;
+                let samples: i64 = row.try_get("samples")?;
+                let avg = |prefix: &str| row.try_get::<f64, _>(format!("{}_avg", prefix).as_str());
+                let range = |prefix: &str| -> anyhow::Result<MetricRange> {
+                    Ok(MetricRange {
+                        min: row.try_get(format!("{}_min", prefix).as_str())?,
+                        max: row.try_get(format!("{}_max", prefix).as_str())?,
+                    })
+                };
+                Ok(StateRecord {
+                    upload_time: bucket_time as u64,
+                    state: State {
+                        cpu_usage: avg("cpu")?,
+                        mem_used: avg("mem")? as u64,
+                        net_in_speed: avg("net_in")? as u64,
+                        net_out_speed: avg("net_out")? as u64,
+                        ..Default::default()
+                    },
+                    rollup: Some(RollupInfo {
+                        resolution,
+                        samples: samples as u64,
+                        cpu_usage: range("cpu")?,
+                        mem_used: range("mem")?,
+                        net_in_speed: range("net_in")?,
+                        net_out_speed: range("net_out")?,
+                    }),
+                })
+            })
+            .collect()
+    }
+
+    /// 查询时间范围内的原始状态，每个 `step` 秒的桶取最早的一条
+    async fn query_raw_states(
+        &self,
+        tenant_id: &str,
+        server_id: u64,
+        from: u64,
+        to: u64,
+        step: u64,
+        limit: u32,
+    ) -> anyhow::Result<Vec<StateRecord>> {
+        let rows = sqlx::query(
+            "SELECT upload_time, state FROM (
+                 SELECT upload_time, state,
+                     ROW_NUMBER() OVER (PARTITION BY upload_time / ? ORDER BY upload_time) AS row_number
+                 FROM states
+                 WHERE tenant_id = ? AND server_id = ? AND upload_time BETWEEN ? AND ?
+             )
+             WHERE row_number = 1
+             ORDER BY upload_time
+             LIMIT ?",
+        )
+        .bind(sql_bound(step))
+        .bind(tenant_id)
+        .bind(sql_int(server_id, "探针ID")?)
+        .bind(sql_bound(from))
+        .bind(sql_bound(to))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let upload_time: i64 = row.try_get("upload_time")?;
+                let state: String = row.try_get("state")?;
+                Ok(StateRecord {
+                    upload_time: upload_time as u64,
+                    state: serde_json::from_str(&state)?,
+                    rollup: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// 将数值转换为数据库整数，超出 i64 范围时返回错误
+fn sql_int(value: u64, name: &str) -> anyhow::Result<i64> {
+    i64::try_from(value).map_err(|_| anyhow::anyhow!("{} 超出数据库整数范围: {}", name, value))
+}
+
+/// 将时间范围的边界转换为数据库整数，超出 i64 范围时取 i64 的最大值
+fn sql_bound(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// 聚合记录已存在时（补报的状态落入已聚合的时间桶）按样本数合并
+fn rollup_upsert() -> String {
+    let updates = ROLLUP_METRICS
+        .iter()
+        .map(|(prefix, _)| {
+            format!(
+                "{0}_min = MIN({0}_min, excluded.{0}_min), \
+                 {0}_max = MAX({0}_max, excluded.{0}_max), \
+                 {0}_avg = ({0}_avg * samples + excluded.{0}_avg * excluded.samples) \
+                 / (samples + excluded.samples), ",
+                prefix
+            )
+        })
+        .collect::<String>();
+    format!(
+        "ON CONFLICT (tenant_id, server_id, resolution, bucket_time) DO UPDATE SET {}samples = samples + excluded.samples",
+        updates
+    )
+}
+
+/// 聚合表的建表语句，`INSERT INTO state_rollups SELECT` 依赖列的顺序
+fn create_rollups_table(name: &str) -> String {
+    let metric_columns = ROLLUP_METRICS
+        .iter()
+        .map(|(prefix, _)| {
+            format!(
+                "{0}_min REAL NOT NULL, {0}_max REAL NOT NULL, {0}_avg REAL NOT NULL,",
+                prefix
+            )
+        })
+        .collect::<String>();
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            tenant_id TEXT NOT NULL,
+            server_id INTEGER NOT NULL,
+            resolution INTEGER NOT NULL,
+            bucket_time INTEGER NOT NULL,
+            samples INTEGER NOT NULL,
+            {}
+            PRIMARY KEY (tenant_id, server_id, resolution, bucket_time)
+        )",
+        name, metric_columns
+    )
+}
+
+/// 为不区分租户的旧版本数据库添加租户列，已有数据归入默认租户
+/// 聚合表的主键包含租户列，需要重建表
+async fn migrate_tenant_id(pool: &SqlitePool) -> anyhow::Result<()> {
+    if !has_column(pool, "states", "tenant_id").await? {
+        tracing::info!("为状态表添加租户列");
+        sqlx::query("ALTER TABLE states ADD COLUMN tenant_id TEXT NOT NULL DEFAULT ''")
+            .execute(pool)
+            .await?;
+    }
+    if !has_column(pool, "state_rollups", "tenant_id").await? {
+        tracing::info!("重建聚合表以添加租户列");
+        let mut tx = pool.begin().await?;
+        sqlx::query("ALTER TABLE state_rollups RENAME TO state_rollups_old")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&create_rollups_table("state_rollups"))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO state_rollups SELECT '', * FROM state_rollups_old")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DROP TABLE state_rollups_old")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+/// 判断表中是否存在指定的列
+async fn has_column(pool: &SqlitePool, table: &str, column: &str) -> anyhow::Result<bool> {
+    let row = sqlx::query("SELECT 1 FROM pragma_table_info(?) WHERE name = ?")
+        .bind(table)
+        .bind(column)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在临时目录中创建数据库，内存数据库的每个连接互相独立，不能在连接池中共享
+    async fn storage() -> (Storage, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("test.db").display());
+        (Storage::connect_url(&url).await.unwrap(), dir)
+    }
+
+    fn state(cpu_usage: f64) -> State {
+        State {
+            cpu_usage,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn writer_flushes_queue_on_close() {
+        let (storage, _dir) = storage().await;
+        let (writer, task) = storage.spawn_writer(1024);
+        for upload_time in 0..600 {
+            writer
+                .write("", 1, upload_time, &state(upload_time as f64))
+                .unwrap();
+        }
+        drop(writer);
+        task.await.unwrap();
+
+        let records = storage.query_states("", 1, 0, 600, 1, 1000).await.unwrap();
+        assert_eq!(records.len(), 600);
+        assert!(storage.has_server("", 1).await.unwrap());
+        assert!(!storage.has_server("other", 1).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn writer_rejects_out_of_range_values() {
+        let (storage, _dir) = storage().await;
+        let (writer, _task) = storage.spawn_writer(16);
+
+        assert!(writer.write("", u64::MAX, 1, &state(0.0)).is_err());
+        assert!(writer.write("", 1, u64::MAX, &state(0.0)).is_err());
+        assert!(writer.write("", i64::MAX as u64, 1, &state(0.0)).is_ok());
+        assert!(!storage.has_server("", u64::MAX).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_state() {
+        let (storage, _dir) = storage().await;
+        // 后台任务在当前线程运行时中尚未开始执行，队列不会被取走
+        let (writer, _task) = storage.spawn_writer(1);
+
+        assert!(writer.write("", 1, 1, &state(0.0)).is_ok());
+        assert!(writer.write("", 1, 2, &state(0.0)).is_err());
+    }
+
+    #[tokio::test]
+    async fn downsampling_returns_earliest_state_of_each_bucket() {
+        let (storage, _dir) = storage().await;
+        let (writer, task) = storage.spawn_writer(1024);
+        // 乱序写入，每个状态的 CPU 使用率等于上报时间
+        for upload_time in [19, 5, 12, 0, 25, 11, 7, 28] {
+            writer
+                .write("", 1, upload_time, &state(upload_time as f64))
+                .unwrap();
+        }
+        drop(writer);
+        task.await.unwrap();
+
+        let records = storage
+            .query_states("", 1, 0, u64::MAX, 10, 100)
+            .await
+            .unwrap();
+        let times: Vec<u64> = records.iter().map(|record| record.upload_time).collect();
+        assert_eq!(times, vec![0, 11, 25]);
+        for record in &records {
+            assert_eq!(record.state.cpu_usage, record.upload_time as f64);
+        }
+    }
+}
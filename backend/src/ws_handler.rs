@@ -1,16 +1,116 @@
-use common::panda_monitor::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::panda_monitor::{Command, CommandType, State, StateList};
+use prost::Message as _;
+use salvo::http::StatusError;
 use salvo::websocket::{Message, WebSocket, WebSocketUpgrade};
 use salvo::{async_trait, Depot, FlowCtrl, Handler, Request, Response};
-use tokio::sync::broadcast::Sender;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::sync::Mutex;
+
+use crate::auth::TenantAuth;
+use crate::codec::Serialization;
+use crate::rpc_service::SharedState;
+
+// 常量定义
+const SNAPSHOT_TIMEOUT_SECS: u64 = 5; // 等待探针单次上报的最长时间
+
+/// JSON 格式的 WebSocket 消息，如 `{"action":"snapshot"}`
+#[derive(Debug, Deserialize)]
+struct WsMessage {
+    action: String,
+    /// 目标探针 ID，为空时以所有已连接的探针为目标（`refresh_host` 必须指定）
+    /// 未连接的探针收不到命令，会通过 `not_connected` 消息告知
+    #[serde(default)]
+    server_ids: Vec<u64>,
+    /// `set_interval` 的上报间隔（秒），也可以写作 `secs`，探针会限制在安全范围内，停止上报后恢复启动时的间隔
+    #[serde(default, alias = "secs")]
+    interval_secs: Option<u64>,
+}
+
+/// 状态更新帧的编码方式，通过连接地址的 `encoding` 参数指定，未指定时与服务端的 `--serialize` 一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameEncoding {
+    /// 文本帧，内容为状态数组的 JSON
+    Json,
+    /// 二进制帧，内容为状态数组的 MessagePack
+    Msgpack,
+    /// 二进制帧，内容为 protobuf 编码的 `StateList`
+    Protobuf,
+}
+
+impl FrameEncoding {
+    fn parse(value: Option<&str>, serialization: Serialization) -> Option<Self> {
+        match value {
+            None => Some(match serialization {
+                Serialization::Json => Self::Json,
+                Serialization::Msgpack => Self::Msgpack,
+            }),
+            Some("json") => Some(Self::Json),
+            Some("msgpack") => Some(Self::Msgpack),
+            Some("protobuf") => Some(Self::Protobuf),
+            Some(_) => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Msgpack => "msgpack",
+            Self::Protobuf => "protobuf",
+        }
+    }
+
+    /// 把转发的状态更新命令转换为对应编码的消息
+    /// 编码与广播格式一致时直接转发，否则先解析为状态数组再重新编码
+    fn state_frame(self, command: Command, serialization: Serialization) -> Option<Message> {
+        let result = match (self, serialization) {
+            (Self::Json, Serialization::Json) => Ok(Message::text(command.data)),
+            (Self::Msgpack, Serialization::Msgpack) => Ok(Message::binary(command.payload)),
+            _ => serialization
+                .decode(&command)
+                .and_then(|states| self.encode(states)),
+        };
+        result
+            .map_err(|e| tracing::error!("转换状态更新失败: {}", e))
+            .ok()
+    }
+
+    fn encode(self, states: Vec<State>) -> anyhow::Result<Message> {
+        Ok(match self {
+            Self::Json => Message::text(serde_json::to_string(&states)?),
+            Self::Msgpack => Message::binary(rmp_serde::to_vec_named(&states)?),
+            Self::Protobuf => Message::binary(StateList { states }.encode_to_vec()),
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct WsHandler {
     command_tx: Sender<Command>,
+    shared_states: Arc<Mutex<SharedState>>,
+    /// 广播状态更新使用的序列化格式
+    serialization: Serialization,
+    /// 连接只能查看和控制所属租户的探针
+    auth: TenantAuth,
 }
 
 impl WsHandler {
-    pub fn new(command_tx: Sender<Command>) -> Self {
-        Self { command_tx }
+    pub fn new(
+        command_tx: Sender<Command>,
+        shared_states: Arc<Mutex<SharedState>>,
+        serialization: Serialization,
+        auth: TenantAuth,
+    ) -> Self {
+        Self {
+            command_tx,
+            shared_states,
+            serialization,
+            auth,
+        }
     }
 }
 
@@ -23,17 +123,37 @@ impl Handler for WsHandler {
         res: &mut Response,
         _ctrl: &mut FlowCtrl,
     ) {
-        // if let Err(e) = self.verify_token(req).await {
-        //     tracing::error!("Token验证失败: {}", e);
-        //     res.status_code(StatusCode::UNAUTHORIZED);
-        //     return;
-        // }
+        let tenant_id = match self.auth.tenant(req) {
+            Ok(tenant_id) => tenant_id,
+            Err(e) => {
+                res.render(e);
+                return;
+            }
+        };
 
-        tracing::info!("WebSocket连接建立");
+        let serialization = self.serialization;
+        let Some(encoding) = FrameEncoding::parse(req.query::<&str>("encoding"), serialization)
+        else {
+            res.render(
+                StatusError::bad_request().brief("encoding 只支持 json、msgpack 或 protobuf"),
+            );
+            return;
+        };
+
+        tracing::info!(tenant_id = tenant_id.as_str(), "WebSocket连接建立");
         let command_tx = self.command_tx.clone();
+        let shared_states = self.shared_states.clone();
         WebSocketUpgrade::new()
-            .upgrade(req, res, |ws| async move {
-                handle_socket(ws, command_tx).await;
+            .upgrade(req, res, move |ws| async move {
+                handle_socket(
+                    ws,
+                    command_tx,
+                    shared_states,
+                    tenant_id,
+                    encoding,
+                    serialization,
+                )
+                .await;
             })
             .await
             .unwrap_or_else(|e| {
@@ -42,29 +162,14 @@ impl Handler for WsHandler {
     }
 }
 
-impl WsHandler {
-    async fn verify_token(&self, req: &mut Request) -> anyhow::Result<()> {
-        let token = req
-            .headers()
-            .get("Authorization")
-            .and_then(|v| v.to_str().ok())
-            .ok_or_else(|| anyhow::anyhow!("缺少授权token"))?;
-
-        // 实现JWT token验证
-        let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET未配置");
-        let validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
-        let _: jsonwebtoken::TokenData<serde_json::Value> = jsonwebtoken::decode(
-            token,
-            &jsonwebtoken::DecodingKey::from_secret(secret.as_ref()),
-            &validation,
-        )
-        .map_err(|e| anyhow::anyhow!("Token验证失败: {}", e))?;
-
-        Ok(())
-    }
-}
-
-async fn handle_socket(mut socket: WebSocket, command_tx: Sender<Command>) {
+async fn handle_socket(
+    mut socket: WebSocket,
+    command_tx: Sender<Command>,
+    shared_states: Arc<Mutex<SharedState>>,
+    tenant_id: String,
+    encoding: FrameEncoding,
+    serialization: Serialization,
+) {
     while let Some(msg) = socket.recv().await {
         let msg = match msg {
             Ok(msg) => msg,
@@ -76,16 +181,17 @@ async fn handle_socket(mut socket: WebSocket, command_tx: Sender<Command>) {
         if msg.is_close() {
             tracing::info!("WebSocket closed connection");
             let _ = socket.close().await;
-            let result = command_tx.send(Command {
-                command: 0,
-                data: "stop_report_state".into(),
-                server_ids: vec![1, 2, 3],
-            });
-            match result {
-                Ok(ok) => {
-                    tracing::info!("Message sent successfully：{}", ok);
+            let server_ids = shared_states.lock().await.connected_ids(&tenant_id);
+            if !server_ids.is_empty() {
+                let result = command_tx.send(
+                    Command::new(CommandType::StopReportState, server_ids).with_tenant(&tenant_id),
+                );
+                match result {
+                    Ok(ok) => {
+                        tracing::info!("Message sent successfully：{}", ok);
+                    }
+                    Err(e) => tracing::error!("Failed to send message: {}", e),
                 }
-                Err(e) => tracing::error!("Failed to send message: {}", e),
             }
             break;
         }
@@ -97,34 +203,212 @@ async fn handle_socket(mut socket: WebSocket, command_tx: Sender<Command>) {
             }
         };
         tracing::info!("Received message: {}", text);
-        match text {
+        // 同时支持纯文本（如 `start`）和 JSON 格式的消息
+        let WsMessage {
+            action,
+            server_ids,
+            interval_secs,
+        } = serde_json::from_str(text).unwrap_or_else(|_| WsMessage {
+            action: text.to_string(),
+            server_ids: Vec::new(),
+            interval_secs: None,
+        });
+        match action.as_str() {
             "start" => {
-                let result = command_tx.send(Command {
-                    command: 0,
-                    data: "report_state".into(),
-                    server_ids: vec![1, 2, 3],
+                let targets = connected_targets(
+                    &mut socket,
+                    &shared_states,
+                    &tenant_id,
+                    "start",
+                    &server_ids,
+                )
+                .await;
+                if !targets.is_empty() {
+                    let result = command_tx.send(
+                        Command::new(CommandType::ReportState, targets).with_tenant(&tenant_id),
+                    );
+                    match result {
+                        Ok(ok) => {
+                            tracing::info!("Message sent successfully：{}", ok);
+                        }
+                        Err(e) => tracing::error!("Failed to send message: {}", e),
+                    }
+                }
+                let mut rx = command_tx.subscribe();
+                // 订阅成功后先下发一次全量快照，避免在下一次状态上报前页面空白
+                // 快照始终为 JSON，`encoding` 告知后续状态更新帧的编码
+                let snapshot = serde_json::json!({
+                    "type": "snapshot",
+                    "encoding": encoding.as_str(),
+                    "servers": shared_states.lock().await.snapshot(&tenant_id),
                 });
-                match result {
-                    Ok(ok) => {
-                        tracing::info!("Message sent successfully：{}", ok);
+                if let Err(e) = socket.send(Message::text(snapshot.to_string())).await {
+                    tracing::error!("发送快照失败: {}", e);
+                }
+                loop {
+                    let res = match rx.recv().await {
+                        Ok(res) => res,
+                        // 仪表盘处理过慢时丢弃积压的消息，继续推送最新状态，而不是断开连接
+                        Err(RecvError::Lagged(skipped)) => {
+                            tracing::warn!("WebSocket 客户端处理过慢，已跳过 {} 条消息", skipped);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    match res.command() {
+                        // 只转发所属租户的状态更新
+                        CommandType::StateUpdate if res.tenant_id == tenant_id => {
+                            let Some(frame) = encoding.state_frame(res, serialization) else {
+                                continue;
+                            };
+                            if let Err(e) = socket.send(frame).await {
+                                tracing::error!("发送消息失败: {}", e);
+                            }
+                        }
+                        // 服务端下线，通知仪表盘后关闭连接
+                        CommandType::Offline => {
+                            let offline = serde_json::json!({ "type": "offline" });
+                            let _ = socket.send(Message::text(offline.to_string())).await;
+                            let _ = socket.close().await;
+                            return;
+                        }
+                        // 忽略下发给探针的控制命令
+                        _ => {}
                     }
-                    Err(e) => tracing::error!("Failed to send message: {}", e),
                 }
+            }
+
+            // 让探针立即上报一次状态，不开启持续上报
+            "snapshot" => {
+                let targets = connected_targets(
+                    &mut socket,
+                    &shared_states,
+                    &tenant_id,
+                    "snapshot",
+                    &server_ids,
+                )
+                .await;
+                if targets.is_empty() {
+                    continue;
+                }
+                // 先订阅再发送命令，避免错过探针的上报
                 let mut rx = command_tx.subscribe();
-                    while let Ok(res) = rx.recv().await {
-                    // tracing::info!("收到命令: {:?}", res);
-                    if let Err(e) = socket.send(Message::text(res.data)).await {
-                        tracing::error!("发送消息失败: {}", e);
+                let command =
+                    Command::new(CommandType::ReportOnce, targets).with_tenant(&tenant_id);
+                if let Err(e) = command_tx.send(command) {
+                    tracing::error!("Failed to send message: {}", e);
+                    continue;
+                }
+                let timeout = Duration::from_secs(SNAPSHOT_TIMEOUT_SECS);
+                match tokio::time::timeout(timeout, next_state_update(&mut rx, &tenant_id)).await {
+                    Ok(Some(command)) => {
+                        let Some(frame) = encoding.state_frame(command, serialization) else {
+                            continue;
+                        };
+                        if let Err(e) = socket.send(frame).await {
+                            tracing::error!("发送消息失败: {}", e);
+                        }
                     }
+                    Ok(None) => break,
+                    Err(_) => tracing::warn!("等待探针单次上报超时"),
                 }
             }
 
-            "stop" => {
-                let result = command_tx.send(Command {
-                    command: 0,
-                    data: "stop_report_state".into(),
-                    server_ids: vec![1, 2, 3],
+            // 返回当前已知的探针 ID、最近上报时间、在线状态和标签，尚无探针上报时为空数组
+            "list" => {
+                let servers = shared_states.lock().await.list_servers(&tenant_id);
+                match serde_json::to_string(&servers) {
+                    Ok(data) => {
+                        if let Err(e) = socket.send(Message::text(data)).await {
+                            tracing::error!("发送消息失败: {}", e);
+                        }
+                    }
+                    Err(e) => tracing::error!("序列化探针列表失败: {}", e),
+                }
+            }
+
+            // 让指定探针重新上报一次主机信息，如磁盘扩容后
+            "refresh_host" => {
+                let result = if server_ids.is_empty() {
+                    Err("server_ids 不能为空".to_string())
+                } else {
+                    let targets = connected_targets(
+                        &mut socket,
+                        &shared_states,
+                        &tenant_id,
+                        "refresh_host",
+                        &server_ids,
+                    )
+                    .await;
+                    if targets.is_empty() {
+                        Err("指定的探针均未连接".to_string())
+                    } else {
+                        command_tx
+                            .send(
+                                Command::new(CommandType::ReportHost, targets)
+                                    .with_tenant(&tenant_id),
+                            )
+                            .map(|_| ())
+                            .map_err(|_| "没有在线的探针".to_string())
+                    }
+                };
+                let ack = serde_json::json!({
+                    "type": "ack",
+                    "action": "refresh_host",
+                    "server_ids": server_ids,
+                    "success": result.is_ok(),
+                    "message": result.err().unwrap_or_default(),
                 });
+                if let Err(e) = socket.send(Message::text(ack.to_string())).await {
+                    tracing::error!("发送消息失败: {}", e);
+                }
+            }
+
+            // 调整探针的状态上报间隔，无需重启探针，如 `{"action":"set_interval","interval_secs":5}`
+            "set_interval" => {
+                let result = match interval_secs {
+                    None | Some(0) => Err("interval_secs 必须为正整数".to_string()),
+                    Some(secs) => {
+                        let targets = connected_targets(
+                            &mut socket,
+                            &shared_states,
+                            &tenant_id,
+                            "set_interval",
+                            &server_ids,
+                        )
+                        .await;
+                        if targets.is_empty() {
+                            Err("没有已连接的目标探针".to_string())
+                        } else {
+                            command_tx
+                                .send(Command::set_interval(secs, targets).with_tenant(&tenant_id))
+                                .map(|_| ())
+                                .map_err(|_| "没有在线的探针".to_string())
+                        }
+                    }
+                };
+                let ack = serde_json::json!({
+                    "type": "ack",
+                    "action": "set_interval",
+                    "server_ids": server_ids,
+                    "success": result.is_ok(),
+                    "message": result.err().unwrap_or_default(),
+                });
+                if let Err(e) = socket.send(Message::text(ack.to_string())).await {
+                    tracing::error!("发送消息失败: {}", e);
+                }
+            }
+
+            "stop" => {
+                let targets =
+                    connected_targets(&mut socket, &shared_states, &tenant_id, "stop", &server_ids)
+                        .await;
+                if targets.is_empty() {
+                    continue;
+                }
+                let result = command_tx.send(
+                    Command::new(CommandType::StopReportState, targets).with_tenant(&tenant_id),
+                );
                 match result {
                     Ok(ok) => {
                         tracing::info!("Message sent successfully：{}", ok);
@@ -137,3 +421,46 @@ async fn handle_socket(mut socket: WebSocket, command_tx: Sender<Command>) {
         }
     }
 }
+
+/// 筛选出租户当前已连接的目标探针，`requested` 为空时以租户所有已连接的探针为目标
+/// 有探针未连接或没有任何已连接的探针时，向仪表盘发送 `not_connected` 消息，
+/// `server_ids` 为未连接的探针 ID，避免命令被静默丢弃
+async fn connected_targets(
+    socket: &mut WebSocket,
+    shared_states: &Arc<Mutex<SharedState>>,
+    tenant_id: &str,
+    action: &str,
+    requested: &[u64],
+) -> Vec<u64> {
+    let (connected, missing) = shared_states
+        .lock()
+        .await
+        .split_connected(tenant_id, requested);
+    if !missing.is_empty() || connected.is_empty() {
+        let notice = serde_json::json!({
+            "type": "not_connected",
+            "action": action,
+            "server_ids": missing,
+        });
+        if let Err(e) = socket.send(Message::text(notice.to_string())).await {
+            tracing::error!("发送消息失败: {}", e);
+        }
+    }
+    connected
+}
+
+/// 等待租户的下一次状态更新，通道关闭时返回 None
+async fn next_state_update(rx: &mut Receiver<Command>, tenant_id: &str) -> Option<Command> {
+    loop {
+        match rx.recv().await {
+            Ok(command)
+                if command.command() == CommandType::StateUpdate
+                    && command.tenant_id == tenant_id =>
+            {
+                return Some(command)
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
@@ -7,8 +7,8 @@ use std::process::Command;
 fn get_git_version() -> String {
     let version = env::var("CARGO_PKG_VERSION").unwrap().to_string();
 
-    let child = Command::new("git").args(&["describe", "--always"]).output();
-    return match child {
+    let child = Command::new("git").args(["describe", "--always"]).output();
+    match child {
         Ok(child) => {
             let buf = String::from_utf8(child.stdout).expect("failed to read stdout");
             version + "-" + &buf
@@ -17,16 +17,19 @@ fn get_git_version() -> String {
             eprintln!("`git describe` err: {}", err);
             version
         }
-    };
+    }
 }
 
 fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
     tonic_build::configure()
         .build_transport(true)
+        .file_descriptor_set_path(Path::new(&out_dir).join("panda_monitor_descriptor.bin"))
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .message_attribute(".", "#[serde(default)]")
         .compile_protos(&["proto/panda_monitor.proto"], &["proto"])
         .unwrap();
     let version = get_git_version();
-    let mut f = File::create(Path::new(&env::var("OUT_DIR").unwrap()).join("VERSION")).unwrap();
+    let mut f = File::create(Path::new(&out_dir).join("VERSION")).unwrap();
     f.write_all(version.trim().as_bytes()).unwrap();
 }
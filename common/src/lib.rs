@@ -1,3 +1,117 @@
 pub mod panda_monitor {
     tonic::include_proto!("panda_monitor");
 }
+pub mod version;
+
+use std::collections::HashMap;
+
+use panda_monitor::{Command, CommandType};
+
+// 常量定义
+const SET_INTERVAL_PREFIX: &str = "set_interval:"; // 调整上报间隔命令的 data 前缀
+pub const MAX_LABELS: usize = 32; // 每个探针最多上报的标签数
+pub const MAX_LABEL_KEY_LEN: usize = 63; // 标签键的最大长度
+pub const MAX_LABEL_VALUE_LEN: usize = 255; // 标签值的最大字节数
+pub const MAX_TENANT_ID_LEN: usize = 63; // 租户 ID 的最大长度
+
+/// 编译后的 proto 描述符集合，可用于 gRPC 反射等基于 schema 的工具
+pub const DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/panda_monitor_descriptor.bin"));
+
+impl Command {
+    /// 创建指定类型的命令
+    /// `data` 同时填入小写的命令名称，兼容仍按字符串解析命令的旧版本探针，下个版本移除
+    pub fn new(command_type: CommandType, server_ids: Vec<u64>) -> Self {
+        Self {
+            command: command_type.into(),
+            data: command_type.as_str_name().to_ascii_lowercase(),
+            server_ids,
+            ..Default::default()
+        }
+    }
+
+    /// 创建调整状态上报间隔的命令
+    pub fn set_interval(secs: u64, server_ids: Vec<u64>) -> Self {
+        Self {
+            command: CommandType::SetInterval.into(),
+            data: format!("{}{}", SET_INTERVAL_PREFIX, secs),
+            server_ids,
+            ..Default::default()
+        }
+    }
+
+    /// 设置命令所属的租户
+    pub fn with_tenant(mut self, tenant_id: &str) -> Self {
+        self.tenant_id = tenant_id.to_string();
+        self
+    }
+
+    /// 调整上报间隔命令中的秒数，data 格式不正确时返回 None
+    pub fn interval_secs(&self) -> Option<u64> {
+        self.data.strip_prefix(SET_INTERVAL_PREFIX)?.parse().ok()
+    }
+
+    /// 获取命令类型
+    /// 未指定类型时按旧版本的 `data` 字符串（如 `report_state`）解析，下个版本移除
+    /// 旧版本的 `ping` 视为心跳
+    pub fn command_type(&self) -> CommandType {
+        match self.command() {
+            CommandType::Unspecified if self.data.eq_ignore_ascii_case("ping") => {
+                CommandType::Heartbeat
+            }
+            CommandType::Unspecified => CommandType::from_str_name(&self.data.to_ascii_uppercase())
+                .unwrap_or(CommandType::Unspecified),
+            command_type => command_type,
+        }
+    }
+}
+
+/// 校验单个标签
+/// 键只能包含字母、数字、`_`、`-` 和 `.`，以字母或数字开头，最长 `MAX_LABEL_KEY_LEN` 个字符；
+/// 值最长 `MAX_LABEL_VALUE_LEN` 字节
+pub fn validate_label(key: &str, value: &str) -> Result<(), String> {
+    if !is_valid_name(key, MAX_LABEL_KEY_LEN) {
+        return Err(format!(
+            "标签键 {:?} 无效，只能包含字母、数字、_、- 和 .，以字母或数字开头，最长 {} 个字符",
+            key, MAX_LABEL_KEY_LEN
+        ));
+    }
+    if value.len() > MAX_LABEL_VALUE_LEN {
+        return Err(format!(
+            "标签 {} 的值过长，最长 {} 字节",
+            key, MAX_LABEL_VALUE_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// 校验租户 ID，为空表示默认租户
+/// 只能包含字母、数字、`_`、`-` 和 `.`，以字母或数字开头，最长 `MAX_TENANT_ID_LEN` 个字符
+pub fn validate_tenant_id(tenant_id: &str) -> Result<(), String> {
+    if tenant_id.is_empty() || is_valid_name(tenant_id, MAX_TENANT_ID_LEN) {
+        return Ok(());
+    }
+    Err(format!(
+        "租户 ID {:?} 无效，只能包含字母、数字、_、- 和 .，以字母或数字开头，最长 {} 个字符",
+        tenant_id, MAX_TENANT_ID_LEN
+    ))
+}
+
+/// 名称只能包含字母、数字、`_`、`-` 和 `.`，以字母或数字开头，最长 `max_len` 个字符
+fn is_valid_name(name: &str, max_len: usize) -> bool {
+    name.len() <= max_len
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// 校验探针上报的全部标签，数量不能超过 `MAX_LABELS`
+pub fn validate_labels(labels: &HashMap<String, String>) -> Result<(), String> {
+    if labels.len() > MAX_LABELS {
+        return Err(format!("标签数量不能超过 {} 个", MAX_LABELS));
+    }
+    labels
+        .iter()
+        .try_for_each(|(key, value)| validate_label(key, value))
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// 探针版本号，仅比较 `major.minor.patch` 部分
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    /// 解析版本号的 semver 前缀，忽略其后的后缀
+    /// 例如 `0.1.0-ab12cd3` 解析为 `0.1.0`，缺失的部分视为 0
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_start_matches('v');
+        let end = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let mut parts = s[..end].split('.').filter(|part| !part.is_empty());
+
+        let major = parts.next().ok_or_else(|| format!("无效的版本号: {}", s))?;
+        let parse = |part: &str| {
+            part.parse::<u64>()
+                .map_err(|e| format!("无效的版本号 {}: {}", s, e))
+        };
+        let major = parse(major)?;
+        let minor = parts.next().map_or(Ok(0), parse)?;
+        let patch = parts.next().map_or(Ok(0), parse)?;
+        Ok(Self {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}